@@ -4,6 +4,7 @@ import os
 from pathlib import Path
 import json
 import argparse
+import sys
 
 class PoolTableInference():
     def __init__(self, model_path = './yolo_weights.pt',
@@ -17,67 +18,85 @@ class PoolTableInference():
         self.output_dir = output_dir if output_dir else "outputs" 
         self.save = save
         self.save_negative = save_negative
+        self.model = None
 
     def is_empty_dir(self, path):
         with os.scandir(path) as scan:
             return not any(scan)
 
+    def load_model(self):
+        """Load the weights once; later calls reuse them"""
+        if self.model is None:
+            self.model = YOLO(self.model_path)
+            print('Successfully loaded the model weights.')
+        return self.model
+
+    def find_images(self, image_path):
+        """Image files in a path or list of paths, looking inside directories"""
+        image_paths = []
+        input_paths = image_path if isinstance(image_path, list) else [image_path]
+        for input_path in input_paths:
+            if os.path.isfile(input_path):
+                print(f"Found file: {input_path}")  # Debug print
+                image_paths.append(input_path)
+            else:
+                print(f"Looking for images in directory: {input_path}") 
+                found = [str(p) for p in Path(input_path).glob('*')
+                            if p.suffix.lower() in ['.jpg', '.jpeg', '.png']]
+                print(f"Found images: {found}")  # Debug print
+                image_paths.extend(found)
+
+        if not image_paths:
+            print(f"Warning: No valid images found at {image_path}")
+        return image_paths
+
+    def classify_image(self, img_path, save_negative):
+        """Top class of one image; removes it from output_dir when negative unless kept"""
+        results = self.load_model().predict(
+            source=img_path,
+            conf=self.conf_threshold,
+            save=False,   # Save the results
+            project= os.path.dirname(self.output_dir),
+            name=os.path.basename(self.output_dir),
+            exist_ok=True, 
+            verbose=False 
+        )
+
+        # Extract classification results
+        result = {
+            'class_name': results[0].names[results[0].probs.top1],  # Get class name
+            'confidence': float(results[0].probs.top1conf),  # Get confidence
+            'class_index': int(results[0].probs.top1)  # Get class index
+        }
+
+        # Remove photos without pool tables
+        if not save_negative and result['class_name'] == 'no_pool_table':
+            os.remove(os.path.join(self.output_dir, os.path.basename(img_path)))
+        return result
+
     def run_inference(self, image_path, save_negative=None):
         """
-        Run classification inference on a single image or directory of images from same venue
+        Run classification inference on a single image or directory of images from same venue,
+        or on a list of such paths
         """
         # Use instance save_negative if not explicitly provided
         save_negative = save_negative if save_negative is not None else self.save_negative
-        
-        # Load the model
-        model = YOLO(self.model_path)
-        print('Successfully loaded the model weights.')
-
-        image_paths = []
-        if os.path.isfile(image_path):
-            print(f"Found file: {image_path}")  # Debug print
-            image_paths = [image_path]
-        else:
-            print(f"Looking for images in directory: {image_path}") 
-            image_paths = [str(p) for p in Path(image_path).glob('*')
-                        if p.suffix.lower() in ['.jpg', '.jpeg', '.png']]
-            print(f"Found images: {image_paths}")  # Debug print
 
-        if not image_paths:
-            print(f"Warning: No valid images found at {image_path}")
-        
+        image_paths = self.find_images(image_path)
         all_results = {}
         highest_pool_table_conf = 0.0  # Track the highest confidence for pool table
 
         print(f"\nProcessing {image_paths}")
         for img_path in image_paths:
-            # Run inference
-            results = model.predict(
-                source=img_path,
-                conf=self.conf_threshold,
-                save=False,   # Save the results
-                project= os.path.dirname(self.output_dir),
-                name=os.path.basename(self.output_dir),
-                exist_ok=True, 
-                verbose=False 
-            )
-
-            # Extract classification results
-            result = {
-                'class_name': results[0].names[results[0].probs.top1],  # Get class name
-                'confidence': float(results[0].probs.top1conf),  # Get confidence
-                'class_index': int(results[0].probs.top1)  # Get class index
-            }
+            result = self.classify_image(img_path, save_negative)
 
             # If this is a pool table detection, update highest confidence
             if result['class_name'] == 'pool_table':
                 highest_pool_table_conf = max(highest_pool_table_conf, result['confidence'])
             
-            # Remove photos without pool tables
-            if not save_negative and result['class_name'] == 'no_pool_table':
-                os.remove(os.path.join(self.output_dir, os.path.basename(img_path)))
-            
             all_results[os.path.basename(img_path)] = result
+            # One line per image for the Rust side, which keeps per-photo scores
+            print(f"IMAGE_RESULT:{json.dumps({'file': os.path.basename(img_path), **result})}")
             
             print(f"\nProcessed {img_path}")
             print(f"Prediction: {result['class_name']} ({result['confidence']:.2f} confidence)")
@@ -100,14 +119,51 @@ class PoolTableInference():
         #print(f"- JSON results: {results_file}")
 
         return highest_pool_table_conf
-       
+
+    def serve(self):
+        """
+        Worker mode: load the model once, then answer requests read from stdin, one JSON
+        object per line: {"id": 1, "images": [...], "output_dir": "...", "save_negative": false}.
+        Each image gets an {"id": 1, "type": "image", "file": ..., "class_name": ...,
+        "confidence": ..., "class_index": ...} line and the request ends with a
+        "done" line carrying the venue probability, or an "error" line.
+        """
+        protocol = sys.stdout
+        # Everything else printed goes to stderr, so stdout only carries the protocol
+        sys.stdout = sys.stderr
+
+        def send(message):
+            protocol.write(json.dumps(message) + '\n')
+            protocol.flush()
+
+        self.load_model()
+        send({'type': 'ready'})
+        for line in sys.stdin:
+            if not line.strip():
+                continue
+            request_id = None
+            try:
+                request = json.loads(line)
+                request_id = request.get('id')
+                self.output_dir = request.get('output_dir') or self.output_dir
+                save_negative = request.get('save_negative', self.save_negative)
+                highest_pool_table_conf = 0.0
+                for img_path in self.find_images(request['images']):
+                    result = self.classify_image(img_path, save_negative)
+                    if result['class_name'] == 'pool_table':
+                        highest_pool_table_conf = max(highest_pool_table_conf, result['confidence'])
+                    send({'id': request_id, 'type': 'image', 'file': os.path.basename(img_path), **result})
+                send({'id': request_id, 'type': 'done', 'probability': highest_pool_table_conf})
+            except Exception as e:
+                send({'id': request_id, 'type': 'error', 'message': str(e)})
+
 
 if __name__ == "__main__":
     parser = argparse.ArgumentParser(description="A script that accepts command line arguments")
     
     # Add arguments
-    parser.add_argument('--input_path','-i',
-                       help='Path to the input image')
+    parser.add_argument('--input_path','-i', nargs='+',
+                       help='Paths to the input images or image directories')
     parser.add_argument('--model_path','-m',
                        help='Path to the weight file')
     parser.add_argument('-o', '--save_path',
@@ -116,6 +172,8 @@ if __name__ == "__main__":
                        type=lambda x: x.lower() == 'true',
                        default=False,
                        help='Whether to save images without pool tables')
+    parser.add_argument('--worker', action='store_true',
+                       help='Stay running and take requests as JSON lines on stdin')
     
     # Parse arguments
     args = parser.parse_args()
@@ -126,5 +184,8 @@ if __name__ == "__main__":
         output_dir=args.save_path,
         save_negative=args.save_negative
     )
+    if args.worker:
+        engine.serve()
+        sys.exit(0)
     pool_table_probability = engine.run_inference(image_path=args.input_path) 
     print(f"VENUE_PROBABILITY:{pool_table_probability}") 
\ No newline at end of file
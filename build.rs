@@ -1,7 +0,0 @@
-fn main() {
-    // Explicitly configure PyO3
-    // pyo3_build_config::use_pyo3_cfgs();
-
-    // println!("cargo:rustc-link-search=/opt/homebrew/Caskroom/miniconda/base/lib");
-    // println!("cargo:rerun-if-env-changed=PYO3_PYTHON");
-} 
\ No newline at end of file
@@ -0,0 +1,323 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use crate::native_classifier::{is_onnx_model, shared_classifier};
+use crate::python_worker::{run_python_worker, PythonWorkerSettings};
+use crate::tiled_classifier::{TileRegion, TiledClassifier, TilingSettings};
+
+// What the classifier made of one venue folder
+#[derive(Default, Debug)]
+pub struct Inference {
+    // Highest pool-table confidence over the photos
+    pub probability: f32,
+    // The result for each photo, by file name
+    pub images: HashMap<String, ImageResult>,
+}
+
+// The top class of one photo, e.g. an IMAGE_RESULT line printed by PoolTableInference.py
+#[derive(Deserialize, Debug, Clone)]
+pub struct ImageResult {
+    pub file: String,
+    pub class_name: String,
+    pub confidence: f32,
+    // Index of class_name in the model's classes, when the backend knows it
+    #[serde(default)]
+    pub class_index: Option<usize>,
+    // The tile of the photo that gave this result in tiled mode, None for the whole photo
+    #[serde(default)]
+    pub tile: Option<TileRegion>,
+}
+
+impl ImageResult {
+    // The classifier reports its top class; turn that into the chance of a pool table
+    pub fn pool_table_score(&self) -> f32 {
+        if self.class_name == "pool_table" {
+            self.confidence
+        } else {
+            1.0 - self.confidence
+        }
+    }
+}
+
+impl Inference {
+    // Keep one photo's result; the venue probability is the best pool-table confidence
+    pub fn record(&mut self, result: ImageResult) {
+        if result.class_name == "pool_table" {
+            self.probability = self.probability.max(result.confidence);
+        }
+        self.images.insert(result.file.clone(), result);
+    }
+}
+
+// Scores a venue's photos, all in one local folder. Classifiers block for seconds at
+// a time, so callers run them off the async workers. Unless `save_negative` is set,
+// a classifier may remove photos without a table from the folder.
+pub trait Classifier: Send + Sync {
+    fn name(&self) -> String;
+
+    fn classify(&self, image_paths: &[PathBuf], save_negative: bool) -> Result<Inference, Box<dyn std::error::Error>>;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClassifierBackend {
+    // The ONNX runtime for an .onnx model, otherwise the Python worker when it is
+    // enabled and the Python script when not
+    Auto,
+    Python,
+    PythonWorker,
+    Onnx,
+    // Scores from a fixture file, for running the pipeline without a model
+    Mock,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ClassifierSettings {
+    pub backend: ClassifierBackend,
+    // JSON file of scores for the mock backend
+    pub fixture_path: Option<String>,
+    // Classify overlapping tiles of each photo as well, for small, distant tables
+    pub tiling: TilingSettings,
+}
+
+impl Default for ClassifierSettings {
+    fn default() -> Self {
+        ClassifierSettings {
+            backend: ClassifierBackend::Auto,
+            fixture_path: None,
+            tiling: TilingSettings::default(),
+        }
+    }
+}
+
+// The classifier to use. CLASSIFIER_BACKEND and CLASSIFIER_FIXTURE in the environment
+// override the config, so a test run can swap in the mock without editing it.
+// With tiling enabled, the backend is wrapped to classify tiles of each photo.
+pub fn classifier_for(
+    settings: &ClassifierSettings,
+    model_path: &str,
+    worker: &PythonWorkerSettings,
+) -> Result<Arc<dyn Classifier>, Box<dyn std::error::Error>> {
+    let backend = match env::var("CLASSIFIER_BACKEND") {
+        Ok(name) => serde_json::from_value(serde_json::Value::String(name.to_lowercase()))
+            .map_err(|_| format!("Unknown CLASSIFIER_BACKEND \"{}\", expected auto, python, python_worker, onnx or mock", name))?,
+        Err(_) => settings.backend,
+    };
+    let backend = match backend {
+        ClassifierBackend::Auto if is_onnx_model(model_path) => ClassifierBackend::Onnx,
+        ClassifierBackend::Auto if worker.enabled => ClassifierBackend::PythonWorker,
+        ClassifierBackend::Auto => ClassifierBackend::Python,
+        backend => backend,
+    };
+
+    let classifier: Arc<dyn Classifier> = match backend {
+        ClassifierBackend::Python | ClassifierBackend::Auto => Arc::new(PythonScriptClassifier { model_path: model_path.to_string() }),
+        ClassifierBackend::PythonWorker => Arc::new(PythonWorkerClassifier { model_path: model_path.to_string(), settings: worker.clone() }),
+        ClassifierBackend::Onnx => Arc::new(OnnxClassifier { model_path: model_path.to_string() }),
+        ClassifierBackend::Mock => {
+            let fixture_path = env::var("CLASSIFIER_FIXTURE").ok()
+                .or_else(|| settings.fixture_path.clone())
+                .ok_or("The mock classifier needs a fixture_path or CLASSIFIER_FIXTURE")?;
+            Arc::new(MockClassifier::load(Path::new(&fixture_path))?)
+        },
+    };
+    if settings.tiling.enabled {
+        return Ok(Arc::new(TiledClassifier::new(classifier, settings.tiling.clone())));
+    }
+    Ok(classifier)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+// The folder the scripts treat as their output directory, where negatives are removed
+fn image_folder(image_paths: &[PathBuf]) -> PathBuf {
+    image_paths
+        .first()
+        .and_then(|path| path.parent())
+        .map(Path::to_path_buf)
+        .unwrap_or_default()
+}
+
+// PoolTableInference.py, started once per venue
+pub struct PythonScriptClassifier {
+    model_path: String,
+}
+
+impl Classifier for PythonScriptClassifier {
+    fn name(&self) -> String {
+        format!("Python script with {}", self.model_path)
+    }
+
+    fn classify(&self, image_paths: &[PathBuf], save_negative: bool) -> Result<Inference, Box<dyn std::error::Error>> {
+        let output = Command::new("python3")
+            .arg("PoolTableInference.py")
+            .arg("-i")
+            .args(image_paths)
+            .arg("-m")
+            .arg(&self.model_path)
+            .arg("-o")
+            .arg(image_folder(image_paths))
+            .arg("--save-negative")
+            .arg(save_negative.to_string())
+            .output()?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            println!("Error running Python script: {}", error);
+            return Err(error.into());
+        }
+
+        // Parse the output to get probability
+        // If we don't find a probability, 0.0 is the default
+        let mut inference = Inference::default();
+        let output_str = String::from_utf8_lossy(&output.stdout);
+        for line in output_str.lines() {
+            if let Some(result) = line.strip_prefix("IMAGE_RESULT:") {
+                match serde_json::from_str::<ImageResult>(result) {
+                    Ok(result) => inference.record(result),
+                    Err(e) => eprintln!("Unreadable image result {}: {}", result, e),
+                }
+            } else if line.starts_with("VENUE_PROBABILITY:") {
+                if let Ok(prob) = line.strip_prefix("VENUE_PROBABILITY:").unwrap().trim().parse::<f32>() {
+                    inference.probability = prob;
+                }
+            }
+        }
+
+        Ok(inference)
+    }
+}
+
+// PoolTableInference.py kept running with the model loaded
+pub struct PythonWorkerClassifier {
+    model_path: String,
+    settings: PythonWorkerSettings,
+}
+
+impl Classifier for PythonWorkerClassifier {
+    fn name(&self) -> String {
+        format!("Python worker with {}", self.model_path)
+    }
+
+    fn classify(&self, image_paths: &[PathBuf], save_negative: bool) -> Result<Inference, Box<dyn std::error::Error>> {
+        run_python_worker(image_paths, &self.model_path, &image_folder(image_paths), save_negative, &self.settings)
+    }
+}
+
+// The in-process ONNX runtime. Like the script, negatives are removed from the
+// folder unless they are kept.
+pub struct OnnxClassifier {
+    model_path: String,
+}
+
+impl Classifier for OnnxClassifier {
+    fn name(&self) -> String {
+        format!("ONNX runtime with {}", self.model_path)
+    }
+
+    fn classify(&self, image_paths: &[PathBuf], save_negative: bool) -> Result<Inference, Box<dyn std::error::Error>> {
+        let classifier = shared_classifier(&self.model_path)?;
+        let mut inference = Inference::default();
+        for path in image_paths {
+            let prediction = classifier.classify(path)?;
+            let result = ImageResult {
+                file: file_name(path),
+                class_name: prediction.class_name,
+                confidence: prediction.confidence,
+                class_index: Some(prediction.class_index),
+                tile: None,
+            };
+            println!("Prediction for {}: {} ({:.2} confidence)", path.display(), result.class_name, result.confidence);
+
+            if result.class_name != "pool_table" && !save_negative {
+                std::fs::remove_file(path)?;
+            }
+            inference.record(result);
+        }
+        Ok(inference)
+    }
+}
+
+// One fixture entry: the top class the mock reports
+#[derive(Deserialize, Debug, Clone)]
+struct MockResult {
+    class_name: String,
+    confidence: f32,
+    #[serde(default)]
+    class_index: Option<usize>,
+}
+
+// Fixture file for the mock classifier:
+// {
+//   "images": {
+//     "ChIJN1t_tDeuEmsRUsoyG83frY4_0.jpg": {"class_name": "pool_table", "confidence": 0.97, "class_index": 1},
+//     "ChIJ3S-JXmauEmsRUcIaWtf4MzE_": {"class_name": "pool_table", "confidence": 0.8}
+//   },
+//   "default": {"class_name": "no_pool_table", "confidence": 0.9}
+// }
+// A key matches the file name exactly, or else as a prefix, the longest prefix
+// winning; "<place_id>_" covers every photo of a venue. Photos matching nothing get
+// `default`, or fail the run when there is none.
+#[derive(Deserialize, Debug)]
+struct MockFixture {
+    #[serde(default)]
+    images: HashMap<String, MockResult>,
+    #[serde(default)]
+    default: Option<MockResult>,
+}
+
+// Deterministic scores from a fixture file. It never touches the photos; negatives
+// are still deleted from storage with the scores.
+pub struct MockClassifier {
+    fixture_path: String,
+    fixture: MockFixture,
+}
+
+impl MockClassifier {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read classifier fixture {}: {}", path.display(), e))?;
+        let fixture = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid classifier fixture {}: {}", path.display(), e))?;
+        Ok(MockClassifier { fixture_path: path.display().to_string(), fixture })
+    }
+
+    fn result_for(&self, file: &str) -> Option<&MockResult> {
+        self.fixture.images.get(file)
+            .or_else(|| self.fixture.images
+                .iter()
+                .filter(|(key, _)| file.starts_with(key.as_str()))
+                .max_by_key(|(key, _)| key.len())
+                .map(|(_, result)| result))
+            .or(self.fixture.default.as_ref())
+    }
+}
+
+impl Classifier for MockClassifier {
+    fn name(&self) -> String {
+        format!("mock classifier from {}", self.fixture_path)
+    }
+
+    fn classify(&self, image_paths: &[PathBuf], _save_negative: bool) -> Result<Inference, Box<dyn std::error::Error>> {
+        let mut inference = Inference::default();
+        for path in image_paths {
+            let file = file_name(path);
+            let result = self.result_for(&file)
+                .ok_or_else(|| format!("{} is not in classifier fixture {}", file, self.fixture_path))?;
+            inference.record(ImageResult {
+                file,
+                class_name: result.class_name.clone(),
+                confidence: result.confidence,
+                class_index: result.class_index,
+                tile: None,
+            });
+        }
+        Ok(inference)
+    }
+}
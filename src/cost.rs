@@ -0,0 +1,194 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+// The billable Google API calls we make
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Sku {
+    NearbySearch,
+    TextSearch,
+    PlaceDetails,
+    PlacePhoto,
+    Geocoding,
+}
+
+impl Sku {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sku::NearbySearch => "nearby_search",
+            Sku::TextSearch => "text_search",
+            Sku::PlaceDetails => "place_details",
+            Sku::PlacePhoto => "place_photo",
+            Sku::Geocoding => "geocoding",
+        }
+    }
+}
+
+// USD per 1000 calls. Defaults are Google's list prices for the field masks we request;
+// adjust them for your billing tier or free monthly credit.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PriceTable {
+    pub nearby_search: f64,
+    pub text_search: f64,
+    pub place_details: f64,
+    pub place_photo: f64,
+    pub geocoding: f64,
+}
+
+impl Default for PriceTable {
+    fn default() -> Self {
+        PriceTable {
+            nearby_search: 35.0,
+            text_search: 35.0,
+            place_details: 25.0,
+            place_photo: 7.0,
+            geocoding: 5.0,
+        }
+    }
+}
+
+impl PriceTable {
+    pub fn price_per_call(&self, sku: Sku) -> f64 {
+        let per_thousand = match sku {
+            Sku::NearbySearch => self.nearby_search,
+            Sku::TextSearch => self.text_search,
+            Sku::PlaceDetails => self.place_details,
+            Sku::PlacePhoto => self.place_photo,
+            Sku::Geocoding => self.geocoding,
+        };
+        per_thousand / 1000.0
+    }
+}
+
+// Counts billable calls during one run and compares the estimated spend to the budget
+pub struct CostTracker {
+    prices: PriceTable,
+    budget: Option<f64>,
+    counts: Mutex<BTreeMap<Sku, u64>>,
+}
+
+impl CostTracker {
+    pub fn new(prices: PriceTable, budget: Option<f64>) -> Self {
+        CostTracker {
+            prices,
+            budget,
+            counts: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn record(&self, sku: Sku) {
+        *self.counts.lock().unwrap().entry(sku).or_insert(0) += 1;
+    }
+
+    pub fn estimated_cost(&self) -> f64 {
+        self.counts
+            .lock()
+            .unwrap()
+            .iter()
+            // Summing floats starts from -0.0, which would print as "$-0.00"
+            .fold(0.0, |total, (sku, count)| total + self.prices.price_per_call(*sku) * *count as f64)
+    }
+
+    pub fn budget_exhausted(&self) -> bool {
+        self.budget.map(|budget| self.estimated_cost() >= budget).unwrap_or(false)
+    }
+
+    // e.g. "API usage: 12 nearby_search, 40 place_photo; estimated cost $0.66 of $5.00 budget"
+    pub fn summary(&self) -> String {
+        let counts = self.counts.lock().unwrap();
+        let calls = if counts.is_empty() {
+            "no billable calls".to_string()
+        } else {
+            counts
+                .iter()
+                .map(|(sku, count)| format!("{} {}", count, sku.name()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        drop(counts);
+
+        let budget = match self.budget {
+            Some(budget) => format!(" of ${:.2} budget", budget),
+            None => String::new(),
+        };
+        format!("API usage: {}; estimated cost ${:.2}{}", calls, self.estimated_cost(), budget)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Prices that are exact in binary, so spend can sit exactly on the budget
+    fn prices() -> PriceTable {
+        PriceTable {
+            nearby_search: 500.0,
+            text_search: 125.0,
+            place_details: 0.0,
+            place_photo: 250.0,
+            geocoding: 5.0,
+        }
+    }
+
+    #[test]
+    fn cost_is_summed_per_sku() {
+        let costs = CostTracker::new(prices(), None);
+        costs.record(Sku::NearbySearch);
+        costs.record(Sku::PlacePhoto);
+        costs.record(Sku::PlacePhoto);
+        costs.record(Sku::TextSearch);
+        costs.record(Sku::PlaceDetails);
+
+        assert_eq!(costs.estimated_cost(), 0.5 + 2.0 * 0.25 + 0.125);
+        assert_eq!(PriceTable::default().price_per_call(Sku::NearbySearch), 0.035);
+        assert_eq!(PriceTable::default().price_per_call(Sku::Geocoding), 0.005);
+    }
+
+    #[test]
+    fn the_budget_is_exhausted_once_spend_reaches_it() {
+        let costs = CostTracker::new(prices(), Some(1.0));
+        assert!(!costs.budget_exhausted());
+        costs.record(Sku::NearbySearch);
+        costs.record(Sku::PlacePhoto);
+        assert!(!costs.budget_exhausted());
+
+        // Exactly at the limit, then just over it
+        costs.record(Sku::PlacePhoto);
+        assert_eq!(costs.estimated_cost(), 1.0);
+        assert!(costs.budget_exhausted());
+        costs.record(Sku::TextSearch);
+        assert!(costs.budget_exhausted());
+
+        let just_under = CostTracker::new(prices(), Some(1.0 + 1e-9));
+        just_under.record(Sku::NearbySearch);
+        just_under.record(Sku::NearbySearch);
+        assert!(!just_under.budget_exhausted());
+    }
+
+    #[test]
+    fn without_a_budget_spend_is_never_exhausted() {
+        let costs = CostTracker::new(prices(), None);
+        for _ in 0..1000 {
+            costs.record(Sku::NearbySearch);
+        }
+        assert_eq!(costs.estimated_cost(), 500.0);
+        assert!(!costs.budget_exhausted());
+    }
+
+    #[test]
+    fn summary_lists_calls_in_sku_order_and_the_budget() {
+        let costs = CostTracker::new(prices(), Some(5.0));
+        assert_eq!(costs.summary(), "API usage: no billable calls; estimated cost $0.00 of $5.00 budget");
+
+        costs.record(Sku::PlacePhoto);
+        costs.record(Sku::NearbySearch);
+        costs.record(Sku::PlacePhoto);
+        assert_eq!(costs.summary(), "API usage: 1 nearby_search, 2 place_photo; estimated cost $1.00 of $5.00 budget");
+
+        let unbudgeted = CostTracker::new(prices(), None);
+        unbudgeted.record(Sku::Geocoding);
+        assert_eq!(unbudgeted.summary(), "API usage: 1 geocoding; estimated cost $0.01");
+    }
+}
@@ -0,0 +1,385 @@
+use serde::{Serialize, Deserialize};
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use crate::cost::{CostTracker, Sku};
+use crate::google_api::{parse_json, send_with_retry, GoogleApiError, RetryPolicy};
+use crate::{GeocoderBackend, GeocodingSettings};
+
+const GOOGLE_GEOCODE_URL: &str = "https://maps.googleapis.com/maps/api/geocode/json";
+
+// Where a place name or address ended up, echoed back with the search results
+#[derive(Serialize, Debug, Clone)]
+pub struct GeocodedLocation {
+    pub query: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    // What the backend matched, e.g. "Lexington, MA, US" or a full street address
+    pub matched_name: String,
+    pub source: String,
+}
+
+// Turns a city name or street address into coordinates
+pub trait Geocoder: Send + Sync {
+    fn geocode<'a>(&'a self, query: &'a str) -> BoxFuture<'a, Result<GeocodedLocation, Box<dyn std::error::Error>>>;
+}
+
+pub fn geocoder_for<'a>(
+    settings: &GeocodingSettings,
+    api_key: &str,
+    retry: &RetryPolicy,
+    costs: &'a CostTracker,
+) -> Box<dyn Geocoder + 'a> {
+    match settings.backend {
+        GeocoderBackend::Gazetteer => Box::new(GazetteerGeocoder::new(&settings.gazetteer_path)),
+        GeocoderBackend::Google => Box::new(GoogleGeocoder::new(api_key, GOOGLE_GEOCODE_URL, retry, costs)),
+    }
+}
+
+struct GazetteerEntry {
+    name: String,
+    alternate_names: Vec<String>,
+    admin1: String,
+    country: String,
+    latitude: f64,
+    longitude: f64,
+    population: u64,
+}
+
+impl GazetteerEntry {
+    // Either a GeoNames cities*.txt row (19 columns) or our own short layout:
+    // name, admin1, country, latitude, longitude, population, alternate names
+    fn parse(line: &str) -> Option<GazetteerEntry> {
+        let cols: Vec<&str> = line.split('\t').collect();
+        let split_names = |names: &str| -> Vec<String> {
+            names.split(',').map(|n| n.trim().to_lowercase()).filter(|n| !n.is_empty()).collect()
+        };
+
+        if cols.len() >= 19 {
+            let mut alternate_names = split_names(cols[3]);
+            alternate_names.push(cols[2].to_lowercase());
+            Some(GazetteerEntry {
+                name: cols[1].to_string(),
+                alternate_names,
+                admin1: cols[10].to_string(),
+                country: cols[8].to_string(),
+                latitude: cols[4].parse().ok()?,
+                longitude: cols[5].parse().ok()?,
+                population: cols[14].parse().unwrap_or(0),
+            })
+        } else if cols.len() >= 6 {
+            Some(GazetteerEntry {
+                name: cols[0].to_string(),
+                alternate_names: cols.get(6).map(|n| split_names(n)).unwrap_or_default(),
+                admin1: cols[1].to_string(),
+                country: cols[2].to_string(),
+                latitude: cols[3].parse().ok()?,
+                longitude: cols[4].parse().ok()?,
+                population: cols[5].parse().unwrap_or(0),
+            })
+        } else {
+            None
+        }
+    }
+
+    fn has_name(&self, name: &str) -> bool {
+        self.name.to_lowercase() == name || self.alternate_names.iter().any(|n| n == name)
+    }
+
+    // "MA", "MA 02421", "US" and "USA" all qualify a Massachusetts city
+    fn matches_qualifier(&self, qualifier: &str) -> bool {
+        let matches_code = |code: &str| {
+            let code = if code == "usa" { "us" } else { code };
+            code.eq_ignore_ascii_case(&self.admin1) || code.eq_ignore_ascii_case(&self.country)
+        };
+        matches_code(qualifier) || qualifier.split_whitespace().any(matches_code)
+    }
+}
+
+// Parsed gazetteers by path, so a file is read once per process rather than on every lookup
+static GAZETTEERS: OnceLock<Mutex<HashMap<String, Arc<Vec<GazetteerEntry>>>>> = OnceLock::new();
+
+fn load_gazetteer(path: &str) -> Result<Arc<Vec<GazetteerEntry>>, Box<dyn std::error::Error>> {
+    let mut gazetteers = GAZETTEERS.get_or_init(Default::default).lock().unwrap();
+    if let Some(entries) = gazetteers.get(path) {
+        return Ok(entries.clone());
+    }
+
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read gazetteer {}: {}", path, e))?;
+    let entries: Arc<Vec<GazetteerEntry>> = Arc::new(content
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+        .filter_map(GazetteerEntry::parse)
+        .collect());
+    gazetteers.insert(path.to_string(), entries.clone());
+    Ok(entries)
+}
+
+// Offline lookup of city names in a bundled gazetteer file.
+// Street addresses are resolved to the city they name, so precision is city level.
+pub struct GazetteerGeocoder {
+    path: String,
+}
+
+impl GazetteerGeocoder {
+    pub fn new(path: &str) -> Self {
+        GazetteerGeocoder { path: path.to_string() }
+    }
+
+    fn lookup(&self, query: &str) -> Result<GeocodedLocation, Box<dyn std::error::Error>> {
+        let entries = load_gazetteer(&self.path)?;
+
+        // "123 Main St, Lexington, MA 02421": try each comma-separated part as the city
+        // name, treating the parts after it as state/country qualifiers. The entries
+        // matching the most qualifiers win; if they are in different places it is an error.
+        let parts: Vec<String> = query.split(',').map(|p| p.trim().to_lowercase()).filter(|p| !p.is_empty()).collect();
+        for (i, name) in parts.iter().enumerate() {
+            let qualifiers = &parts[i + 1..];
+            let candidates: Vec<(usize, &GazetteerEntry)> = entries
+                .iter()
+                .filter(|e| e.has_name(name))
+                .map(|e| (qualifiers.iter().filter(|q| e.matches_qualifier(q)).count(), e))
+                .collect();
+            let Some(best_score) = candidates.iter().map(|(score, _)| *score).max() else { continue };
+            let best: Vec<&GazetteerEntry> = candidates
+                .into_iter()
+                .filter(|(score, _)| *score == best_score)
+                .map(|(_, e)| e)
+                .collect();
+
+            // Several states or countries share the name and nothing in the query picks
+            // one; guessing the biggest would silently search the wrong city
+            let mut regions: Vec<String> = best.iter().map(|e| format!("{}, {}, {}", e.name, e.admin1, e.country)).collect();
+            regions.sort();
+            regions.dedup();
+            if regions.len() > 1 {
+                return Err(format!(
+                    "\"{}\" is ambiguous, it could be {}. Add the state or country, e.g. \"{}, {}\"",
+                    query,
+                    regions.join("; "),
+                    best[0].name,
+                    best[0].admin1
+                ).into());
+            }
+
+            if let Some(entry) = best.into_iter().max_by_key(|e| e.population) {
+                return Ok(GeocodedLocation {
+                    query: query.to_string(),
+                    latitude: entry.latitude,
+                    longitude: entry.longitude,
+                    matched_name: format!("{}, {}, {}", entry.name, entry.admin1, entry.country),
+                    source: format!("gazetteer ({})", self.path),
+                });
+            }
+        }
+
+        Err(format!("\"{}\" was not found in the gazetteer {}", query, self.path).into())
+    }
+}
+
+impl Geocoder for GazetteerGeocoder {
+    fn geocode<'a>(&'a self, query: &'a str) -> BoxFuture<'a, Result<GeocodedLocation, Box<dyn std::error::Error>>> {
+        Box::pin(async move { self.lookup(query) })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleGeocodeResponse {
+    status: String,
+    #[serde(default)]
+    results: Vec<GoogleGeocodeResult>,
+    error_message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleGeocodeResult {
+    formatted_address: String,
+    geometry: GoogleGeometry,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleGeometry {
+    location: GoogleLatLng,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleLatLng {
+    lat: f64,
+    lng: f64,
+}
+
+// Google Geocoding API, resolves full street addresses
+pub struct GoogleGeocoder<'a> {
+    api_key: String,
+    url: String,
+    retry: RetryPolicy,
+    costs: &'a CostTracker,
+}
+
+impl<'a> GoogleGeocoder<'a> {
+    pub fn new(api_key: &str, url: &str, retry: &RetryPolicy, costs: &'a CostTracker) -> Self {
+        GoogleGeocoder {
+            api_key: api_key.to_string(),
+            url: url.to_string(),
+            retry: retry.clone(),
+            costs,
+        }
+    }
+
+    async fn lookup(&self, query: &str) -> Result<GeocodedLocation, Box<dyn std::error::Error>> {
+        let client = reqwest::Client::new();
+        let response = send_with_retry(&self.retry, || {
+            client.get(&self.url).query(&[("address", query), ("key", self.api_key.as_str())])
+        }).await?;
+        self.costs.record(Sku::Geocoding);
+        let response: GoogleGeocodeResponse = parse_json(response).await?;
+
+        // The Geocoding API answers 200 and reports problems in its own status field
+        let message = || format!(
+            "{} for \"{}\"{}",
+            response.status,
+            query,
+            response.error_message.as_ref().map(|m| format!(": {}", m)).unwrap_or_default()
+        );
+        match response.status.as_str() {
+            "OK" => {},
+            "ZERO_RESULTS" => return Err(format!("Google Geocoding found nothing for \"{}\"", query).into()),
+            "REQUEST_DENIED" => return Err(GoogleApiError::ApiKey { status: 200, message: message() }.into()),
+            "OVER_QUERY_LIMIT" | "OVER_DAILY_LIMIT" =>
+                return Err(GoogleApiError::Quota { message: message(), retry_after: None }.into()),
+            "INVALID_REQUEST" => return Err(GoogleApiError::InvalidArgument { message: message() }.into()),
+            _ => return Err(GoogleApiError::Other { status: 200, message: message() }.into()),
+        }
+
+        let first = response.results.into_iter().next().ok_or("Google Geocoding returned no results")?;
+        Ok(GeocodedLocation {
+            query: query.to_string(),
+            latitude: first.geometry.location.lat,
+            longitude: first.geometry.location.lng,
+            matched_name: first.formatted_address,
+            source: "google geocoding".to_string(),
+        })
+    }
+}
+
+impl Geocoder for GoogleGeocoder<'_> {
+    fn geocode<'a>(&'a self, query: &'a str) -> BoxFuture<'a, Result<GeocodedLocation, Box<dyn std::error::Error>>> {
+        Box::pin(self.lookup(query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cost::PriceTable;
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn geocode(query: &str) -> Result<GeocodedLocation, Box<dyn std::error::Error>> {
+        GazetteerGeocoder::new("data/gazetteer_cities.tsv").lookup(query)
+    }
+
+    #[test]
+    fn qualified_names_pick_the_named_state() {
+        let lexington_ma = geocode("Lexington, MA").unwrap();
+        assert_eq!(lexington_ma.matched_name, "Lexington, MA, US");
+        assert_eq!((lexington_ma.latitude, lexington_ma.longitude), (42.4473, -71.2245));
+
+        assert_eq!(geocode("lexington, ky").unwrap().matched_name, "Lexington, KY, US");
+        assert_eq!(geocode("123 Main St, Lexington, MA 02421").unwrap().matched_name, "Lexington, MA, US");
+    }
+
+    #[test]
+    fn ambiguous_names_list_the_candidates() {
+        let error = geocode("Lexington").unwrap_err().to_string();
+        assert!(error.contains("ambiguous"), "{}", error);
+        assert!(error.contains("Lexington, KY, US") && error.contains("Lexington, MA, US"), "{}", error);
+
+        // A qualifier neither entry matches leaves it just as ambiguous
+        assert!(geocode("Lexington, Texas").is_err());
+    }
+
+    #[test]
+    fn unique_and_unknown_names() {
+        assert_eq!(geocode("Boston").unwrap().matched_name, "Boston, MA, US");
+        assert!(geocode("Nowhere Special").unwrap_err().to_string().contains("not found"));
+    }
+
+    #[test]
+    fn the_gazetteer_is_parsed_once_per_path() {
+        let first = load_gazetteer("data/gazetteer_cities.tsv").unwrap();
+        geocode("Boston").unwrap();
+        assert!(Arc::ptr_eq(&first, &load_gazetteer("data/gazetteer_cities.tsv").unwrap()));
+        assert!(load_gazetteer("data/no_such_gazetteer.tsv").is_err());
+    }
+
+    // Fails the first request with a 503, then answers like the Geocoding API:
+    // REQUEST_DENIED for the key "bad", otherwise one result
+    async fn geocode_stub(request: HttpRequest, requests: web::Data<AtomicUsize>) -> HttpResponse {
+        if requests.fetch_add(1, Ordering::SeqCst) == 0 {
+            return HttpResponse::ServiceUnavailable().finish();
+        }
+        if request.query_string().contains("key=bad") {
+            return HttpResponse::Ok().json(serde_json::json!({
+                "status": "REQUEST_DENIED",
+                "error_message": "The provided API key is invalid.",
+                "results": [],
+            }));
+        }
+        assert!(request.query_string().contains("address=1+Main+St%2C+Lexington"), "{}", request.query_string());
+        HttpResponse::Ok().json(serde_json::json!({
+            "status": "OK",
+            "results": [{
+                "formatted_address": "1 Main St, Lexington, MA 02421, USA",
+                "geometry": { "location": { "lat": 42.4473, "lng": -71.2245 } },
+            }],
+        }))
+    }
+
+    async fn start_geocode_stub() -> (String, web::Data<AtomicUsize>) {
+        let requests = web::Data::new(AtomicUsize::new(0));
+        let data = requests.clone();
+        let server = HttpServer::new(move || App::new()
+            .app_data(data.clone())
+            .route("/geocode/json", web::get().to(geocode_stub)))
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let url = format!("http://{}/geocode/json", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        (url, requests)
+    }
+
+    fn quick_retry() -> RetryPolicy {
+        RetryPolicy { max_attempts: 3, base_delay_ms: 1, max_delay_ms: 10 }
+    }
+
+    #[actix_web::test]
+    async fn google_geocoding_retries_and_is_billed_once() {
+        let (url, requests) = start_geocode_stub().await;
+        let costs = CostTracker::new(PriceTable::default(), None);
+        let location = GoogleGeocoder::new("key", &url, &quick_retry(), &costs)
+            .geocode("1 Main St, Lexington")
+            .await
+            .unwrap();
+
+        assert_eq!(location.matched_name, "1 Main St, Lexington, MA 02421, USA");
+        assert_eq!((location.latitude, location.longitude), (42.4473, -71.2245));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(costs.summary(), "API usage: 1 geocoding; estimated cost $0.01");
+    }
+
+    #[actix_web::test]
+    async fn a_denied_geocoding_key_is_a_fatal_api_key_error() {
+        let (url, _) = start_geocode_stub().await;
+        let costs = CostTracker::new(PriceTable::default(), None);
+        let error = GoogleGeocoder::new("bad", &url, &quick_retry(), &costs)
+            .geocode("1 Main St, Lexington")
+            .await
+            .unwrap_err();
+
+        assert!(crate::google_api::is_fatal_error(error.as_ref()), "{}", error);
+        assert!(matches!(error.downcast_ref::<GoogleApiError>(), Some(GoogleApiError::ApiKey { .. })));
+        assert!(error.to_string().contains("The provided API key is invalid."), "{}", error);
+    }
+}
\ No newline at end of file
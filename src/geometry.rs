@@ -0,0 +1,96 @@
+// Small helpers for working with search areas on the Earth's surface.
+// Distances are in meters, coordinates are WGS84 degrees.
+
+pub const EARTH_RADIUS_METERS: f64 = 6371000.0;
+
+// Great-circle distance between two points (haversine formula)
+pub fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let lat1_rad = lat1.to_radians();
+    let lat2_rad = lat2.to_radians();
+    let delta_lat = (lat2 - lat1).to_radians();
+    let delta_lon = (lon2 - lon1).to_radians();
+
+    let a = (delta_lat / 2.0).sin().powi(2)
+        + lat1_rad.cos() * lat2_rad.cos() * (delta_lon / 2.0).sin().powi(2);
+
+    let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
+    EARTH_RADIUS_METERS * c
+}
+
+// Move a point by a number of meters north and east.
+// Uses a local flat-earth approximation, fine for the few-kilometer offsets we need.
+pub fn offset_point(lat: f64, lon: f64, north_meters: f64, east_meters: f64) -> (f64, f64) {
+    let delta_lat = (north_meters / EARTH_RADIUS_METERS).to_degrees();
+    let delta_lon = (east_meters / (EARTH_RADIUS_METERS * lat.to_radians().cos())).to_degrees();
+    (lat + delta_lat, lon + delta_lon)
+}
+
+// A circular discovery area, the shape Google's searchNearby accepts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchCircle {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_meters: f64,
+}
+
+impl SearchCircle {
+    pub fn new(latitude: f64, longitude: f64, radius_meters: f64) -> Self {
+        SearchCircle { latitude, longitude, radius_meters }
+    }
+
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        haversine_distance(self.latitude, self.longitude, lat, lon) <= self.radius_meters
+    }
+
+    // Split into four quadrant circles that together cover this one.
+    // Each child is centered on a quadrant of the bounding square and sized to reach
+    // the quadrant's far corner; `overlap` (e.g. 1.1) pads the radius so rounding
+    // near the seams never leaves a gap.
+    pub fn subdivide(&self, overlap: f64) -> Vec<SearchCircle> {
+        let half = self.radius_meters / 2.0;
+        let child_radius = self.radius_meters / std::f64::consts::SQRT_2 * overlap;
+
+        [(half, half), (half, -half), (-half, half), (-half, -half)]
+            .iter()
+            .map(|(north, east)| {
+                let (lat, lon) = offset_point(self.latitude, self.longitude, *north, *east);
+                SearchCircle::new(lat, lon, child_radius)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_point_moves_the_requested_distance() {
+        let (lat, lon) = offset_point(40.0, -75.0, 300.0, -400.0);
+        assert!((haversine_distance(40.0, -75.0, lat, lon) - 500.0).abs() < 1.0);
+        assert!(lat > 40.0 && lon < -75.0);
+    }
+
+    #[test]
+    fn subdivided_children_cover_the_parent() {
+        let parent = SearchCircle::new(40.0, -75.0, 1000.0);
+        let children = parent.subdivide(1.1);
+        assert_eq!(children.len(), 4);
+        assert!(children.iter().all(|c| (c.radius_meters - 1000.0 / std::f64::consts::SQRT_2 * 1.1).abs() < 1e-9));
+
+        // Every corner of every quadrant of the bounding square is inside some child
+        let steps = [-1000.0, 0.0, 1000.0];
+        for north in steps {
+            for east in steps {
+                let (lat, lon) = offset_point(40.0, -75.0, north, east);
+                assert!(children.iter().any(|c| c.contains(lat, lon)), "({}, {}) uncovered", north, east);
+            }
+        }
+        // And so is a ring of points on the parent's edge
+        for degrees in (0..360).step_by(15) {
+            let angle = (degrees as f64).to_radians();
+            let (lat, lon) = offset_point(40.0, -75.0, 999.0 * angle.cos(), 999.0 * angle.sin());
+            assert!(children.iter().any(|c| c.contains(lat, lon)), "{} degrees uncovered", degrees);
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use serde::{Serialize, Deserialize};
+use std::time::Duration;
+use rand::Rng;
+
+// What went wrong talking to a Google API, taken from Google's error JSON:
+// {"error": {"code": 403, "message": "...", "status": "PERMISSION_DENIED"}}
+#[derive(Debug)]
+pub enum GoogleApiError {
+    // Missing, invalid or restricted API key / credentials, or the API is not enabled
+    ApiKey { status: u16, message: String },
+    // The service account could not be read or its OAuth token exchange was refused
+    Auth { message: String },
+    // Rate limit or daily quota exhausted, still failing after retries
+    Quota { message: String, retry_after: Option<Duration> },
+    // Google rejected the request itself (bad field mask, bad place type, ...)
+    InvalidArgument { message: String },
+    // 5xx responses and network failures, still failing after retries
+    Transient { status: Option<u16>, message: String },
+    // Any other non-success status
+    Other { status: u16, message: String },
+    // A success response whose body did not parse
+    InvalidResponse { message: String },
+}
+
+impl std::fmt::Display for GoogleApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GoogleApiError::ApiKey { status, message } =>
+                write!(f, "Google API key or credentials rejected ({}): {}", status, message),
+            GoogleApiError::Auth { message } =>
+                write!(f, "Google OAuth authentication failed: {}", message),
+            GoogleApiError::Quota { message, .. } =>
+                write!(f, "Google API quota exceeded: {}", message),
+            GoogleApiError::InvalidArgument { message } =>
+                write!(f, "Google API rejected the request: {}", message),
+            GoogleApiError::Transient { status: Some(status), message } =>
+                write!(f, "Google API unavailable ({}): {}", status, message),
+            GoogleApiError::Transient { status: None, message } =>
+                write!(f, "Could not reach Google API: {}", message),
+            GoogleApiError::Other { status, message } =>
+                write!(f, "Google API error ({}): {}", status, message),
+            GoogleApiError::InvalidResponse { message } =>
+                write!(f, "Unexpected Google API response: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for GoogleApiError {}
+
+impl GoogleApiError {
+    fn is_retryable(&self) -> bool {
+        matches!(self, GoogleApiError::Quota { .. } | GoogleApiError::Transient { .. })
+    }
+
+    // Errors that will fail every following request too, so a run should stop
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, GoogleApiError::ApiKey { .. } | GoogleApiError::Auth { .. } | GoogleApiError::Quota { .. })
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            GoogleApiError::Quota { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+// True when a boxed error is a GoogleApiError that should stop the run
+pub fn is_fatal_error(error: &(dyn std::error::Error + 'static)) -> bool {
+    error.downcast_ref::<GoogleApiError>().map(|e| e.is_fatal()).unwrap_or(false)
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorEnvelope {
+    error: ErrorBody,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    status: String,
+}
+
+fn classify(status: reqwest::StatusCode, body: &str, retry_after: Option<Duration>) -> GoogleApiError {
+    let (message, google_status) = match serde_json::from_str::<ErrorEnvelope>(body) {
+        Ok(envelope) => (envelope.error.message, envelope.error.status),
+        Err(_) => (body.chars().take(200).collect(), String::new()),
+    };
+    let code = status.as_u16();
+
+    // Google's status string is more specific than the HTTP code (quota errors can
+    // come back as 403), so it decides whenever the body has one
+    match google_status.as_str() {
+        "PERMISSION_DENIED" | "UNAUTHENTICATED" => GoogleApiError::ApiKey { status: code, message },
+        "RESOURCE_EXHAUSTED" => GoogleApiError::Quota { message, retry_after },
+        "INVALID_ARGUMENT" | "FAILED_PRECONDITION" => GoogleApiError::InvalidArgument { message },
+        "UNAVAILABLE" | "INTERNAL" | "DEADLINE_EXCEEDED" => GoogleApiError::Transient { status: Some(code), message },
+        _ => match code {
+            401 | 403 => GoogleApiError::ApiKey { status: code, message },
+            429 => GoogleApiError::Quota { message, retry_after },
+            400 => GoogleApiError::InvalidArgument { message },
+            500..=599 => GoogleApiError::Transient { status: Some(code), message },
+            _ => GoogleApiError::Other { status: code, message },
+        },
+    }
+}
+
+// Retry-After is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().ok()
+}
+
+// How hard to retry rate-limited and failing requests
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay_ms: 500,
+            max_delay_ms: 30000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // "Full jitter" backoff: a random wait up to base * 2^attempt, capped at max_delay
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self.base_delay_ms
+            .saturating_mul(1u64 << attempt.min(20))
+            .min(self.max_delay_ms);
+        Duration::from_millis(rand::thread_rng().gen_range(0..=ceiling))
+    }
+}
+
+// Spaces requests out to at most `per_second`, shared by every task using it.
+// Each caller reserves the next free slot, then sleeps until it comes round.
+pub struct RateLimiter {
+    interval: Option<Duration>,
+    next_slot: tokio::sync::Mutex<tokio::time::Instant>,
+}
+
+impl RateLimiter {
+    // 0 or less means no limit
+    pub fn new(per_second: f64) -> Self {
+        RateLimiter {
+            interval: (per_second > 0.0).then(|| Duration::from_secs_f64(1.0 / per_second)),
+            next_slot: tokio::sync::Mutex::new(tokio::time::Instant::now()),
+        }
+    }
+
+    pub async fn wait(&self) {
+        let Some(interval) = self.interval else { return };
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let slot = (*next_slot).max(tokio::time::Instant::now());
+            *next_slot = slot + interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+// Send a request, retrying 429 and 5xx responses and network errors with jittered
+// backoff (or the server's Retry-After). `build` is called again for every attempt.
+// Returns the successful response, or the typed error from the last attempt.
+pub async fn send_with_retry<F>(policy: &RetryPolicy, build: F) -> Result<reqwest::Response, GoogleApiError>
+where
+    F: Fn() -> reqwest::RequestBuilder,
+{
+    let mut attempt = 0;
+    loop {
+        let error = match build().send().await {
+            Ok(response) if response.status().is_success() => return Ok(response),
+            Ok(response) => {
+                let status = response.status();
+                let retry_after = response.headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(parse_retry_after);
+                let body = response.text().await.unwrap_or_default();
+                classify(status, &body, retry_after)
+            },
+            Err(e) => GoogleApiError::Transient { status: None, message: e.to_string() },
+        };
+
+        attempt += 1;
+        if !error.is_retryable() || attempt >= policy.max_attempts {
+            return Err(error);
+        }
+
+        let delay = error.retry_after()
+            .map(|d| d.min(Duration::from_millis(policy.max_delay_ms)))
+            .unwrap_or_else(|| policy.backoff(attempt));
+        println!("{}; retrying in {:.1}s (attempt {} of {})",
+            error, delay.as_secs_f64(), attempt + 1, policy.max_attempts);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// Read a successful response body as JSON, reporting parse failures as InvalidResponse
+pub async fn parse_json<T: serde::de::DeserializeOwned>(response: reqwest::Response) -> Result<T, GoogleApiError> {
+    let body = response.text().await
+        .map_err(|e| GoogleApiError::Transient { status: None, message: e.to_string() })?;
+    serde_json::from_str(&body).map_err(|e| GoogleApiError::InvalidResponse {
+        message: format!("{} in {}", e, body.chars().take(200).collect::<String>()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    fn kind(error: &GoogleApiError) -> &'static str {
+        match error {
+            GoogleApiError::ApiKey { .. } => "api_key",
+            GoogleApiError::Auth { .. } => "auth",
+            GoogleApiError::Quota { .. } => "quota",
+            GoogleApiError::InvalidArgument { .. } => "invalid_argument",
+            GoogleApiError::Transient { .. } => "transient",
+            GoogleApiError::Other { .. } => "other",
+            GoogleApiError::InvalidResponse { .. } => "invalid_response",
+        }
+    }
+
+    fn google_error(code: u16, status: &str) -> String {
+        serde_json::json!({ "error": { "code": code, "message": "details", "status": status } }).to_string()
+    }
+
+    #[test]
+    fn classify_prefers_googles_status_over_the_http_code() {
+        let cases = [
+            (403, google_error(403, "RESOURCE_EXHAUSTED"), "quota"),
+            (429, google_error(429, "RESOURCE_EXHAUSTED"), "quota"),
+            (403, google_error(403, "PERMISSION_DENIED"), "api_key"),
+            (401, google_error(401, "UNAUTHENTICATED"), "api_key"),
+            (400, google_error(400, "INVALID_ARGUMENT"), "invalid_argument"),
+            (400, google_error(400, "FAILED_PRECONDITION"), "invalid_argument"),
+            (503, google_error(503, "UNAVAILABLE"), "transient"),
+            (504, google_error(504, "DEADLINE_EXCEEDED"), "transient"),
+            // No Google status, the HTTP code decides
+            (403, "Forbidden".to_string(), "api_key"),
+            (429, "Too Many Requests".to_string(), "quota"),
+            (400, google_error(400, ""), "invalid_argument"),
+            (502, "<html>Bad Gateway</html>".to_string(), "transient"),
+            (404, google_error(404, "NOT_FOUND"), "other"),
+        ];
+        for (code, body, expected) in cases {
+            let error = classify(StatusCode::from_u16(code).unwrap(), &body, None);
+            assert_eq!(kind(&error), expected, "{} {}", code, body);
+        }
+    }
+
+    #[test]
+    fn classify_keeps_the_message_and_retry_after() {
+        let wait = Some(Duration::from_secs(7));
+        match classify(StatusCode::FORBIDDEN, &google_error(403, "RESOURCE_EXHAUSTED"), wait) {
+            GoogleApiError::Quota { message, retry_after } => {
+                assert_eq!(message, "details");
+                assert_eq!(retry_after, wait);
+            },
+            other => panic!("expected a quota error, got {:?}", other),
+        }
+        match classify(StatusCode::BAD_GATEWAY, &"x".repeat(500), None) {
+            GoogleApiError::Transient { status, message } => {
+                assert_eq!(status, Some(502));
+                assert_eq!(message.len(), 200);
+            },
+            other => panic!("expected a transient error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn retry_after_is_seconds_or_an_http_date() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::ZERO));
+
+        let in_a_minute = (chrono::Utc::now() + chrono::Duration::seconds(60))
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string();
+        let wait = parse_retry_after(&in_a_minute).unwrap();
+        assert!(wait > Duration::from_secs(55) && wait <= Duration::from_secs(60), "{:?}", wait);
+
+        // A date already gone, or something that is neither
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_retry_after("soon"), None);
+        assert_eq!(parse_retry_after("-5"), None);
+    }
+
+    #[test]
+    fn backoff_stays_under_its_cap() {
+        let policy = RetryPolicy { max_attempts: 5, base_delay_ms: 500, max_delay_ms: 30000 };
+        for attempt in 0..64 {
+            let ceiling = (500u64 << attempt.min(20)).min(30000);
+            for _ in 0..50 {
+                let delay = policy.backoff(attempt);
+                assert!(delay <= Duration::from_millis(ceiling), "attempt {}: {:?}", attempt, delay);
+            }
+        }
+
+        let no_wait = RetryPolicy { base_delay_ms: 0, ..policy };
+        assert_eq!(no_wait.backoff(3), Duration::ZERO);
+    }
+}
@@ -1,13 +1,172 @@
 use serde::{Deserialize, Serialize};
+use futures::StreamExt;
 use jsonwebtoken::{encode, EncodingKey, Header};
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::fs;
+use std::io::Cursor;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use image::{DynamicImage, ImageFormat, ImageOutputFormat};
+use crate::cost::{CostTracker, Sku};
+use tokio::sync::{Mutex, Semaphore};
+use crate::GooglePlacesSettings;
+use crate::google_api::{parse_json, send_with_retry, GoogleApiError, RateLimiter, RetryPolicy};
+use crate::google_places_search::places_url;
+use crate::photo_manifest::{stored_venue_keys, venue_key, PhotoManifest};
+use crate::storage::PhotoStorage;
+
+// Refresh the access token this long before it expires, so a request never carries a stale one
+const TOKEN_REFRESH_MARGIN_SECS: u64 = 300;
+
+// How many photo downloads may run at once, and how fast requests may go out
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DownloadSettings {
+    // Venues whose photos download at the same time
+    pub concurrent_venues: usize,
+    // Photos of one venue downloading at the same time
+    pub concurrent_photos_per_venue: usize,
+    // Photos downloading at the same time across all venues
+    pub concurrent_photos: usize,
+    // Ceiling on place details and photo requests per second, 0 for no limit.
+    // Keep it under the per-minute quota of the API key.
+    pub max_requests_per_second: f64,
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        DownloadSettings {
+            concurrent_venues: 2,
+            concurrent_photos_per_venue: 4,
+            concurrent_photos: 6,
+            max_requests_per_second: 10.0,
+        }
+    }
+}
+
+// A photo that could not be downloaded; the rest of the venue's photos are still used
+#[derive(Debug)]
+pub struct PhotoFailure {
+    pub index: usize,
+    pub photo_name: String,
+    pub error: String,
+}
+
+// Why a downloaded photo was not saved
+#[derive(Debug)]
+pub enum PhotoError {
+    // Google answered with something other than an image, e.g. an error JSON body
+    NotAnImage { content_type: String, body: String },
+    // The connection closed before the whole image arrived
+    Truncated { expected: u64, received: u64 },
+    // The bytes are not in any image format we can read
+    UnknownFormat,
+    // Looked like an image but did not decode, usually a cut-off file
+    Undecodable { format: String, message: String },
+    // Decoded, but could not be re-encoded as JPEG
+    Conversion { format: String, message: String },
+}
+
+impl std::fmt::Display for PhotoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PhotoError::NotAnImage { content_type, body } =>
+                write!(f, "expected an image but got {}: {}", content_type, body),
+            PhotoError::Truncated { expected, received } =>
+                write!(f, "photo truncated: received {} of {} bytes", received, expected),
+            PhotoError::UnknownFormat =>
+                write!(f, "photo is not in a recognized image format"),
+            PhotoError::Undecodable { format, message } =>
+                write!(f, "{} photo could not be decoded: {}", format, message),
+            PhotoError::Conversion { format, message } =>
+                write!(f, "{} photo could not be converted to JPEG: {}", format, message),
+        }
+    }
+}
+
+impl std::error::Error for PhotoError {}
+
+// Photo files the classifier reads; anything else is converted to JPEG on download
+pub const PHOTO_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png"];
+
+pub fn is_photo_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| PHOTO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+// Longest piece of a non-image body kept in the error
+const MAX_ERROR_BODY_CHARS: usize = 200;
+
+// Check a downloaded photo fully decodes and return the bytes to save with their extension.
+// JPEG and PNG are kept as they are; WebP, GIF and the rest are converted to JPEG.
+fn validate_photo(content_type: Option<&str>, expected_len: Option<u64>, bytes: &[u8]) -> Result<(&'static str, Vec<u8>), PhotoError> {
+    if let Some(content_type) = content_type {
+        if !content_type.starts_with("image/") {
+            return Err(PhotoError::NotAnImage {
+                content_type: content_type.to_string(),
+                body: String::from_utf8_lossy(bytes).chars().take(MAX_ERROR_BODY_CHARS).collect(),
+            });
+        }
+    }
+    if let Some(expected) = expected_len {
+        if bytes.len() as u64 != expected {
+            return Err(PhotoError::Truncated { expected, received: bytes.len() as u64 });
+        }
+    }
+
+    let format = image::guess_format(bytes).map_err(|_| PhotoError::UnknownFormat)?;
+    let format_name = format!("{:?}", format);
+    let decoded = image::load_from_memory_with_format(bytes, format)
+        .map_err(|e| PhotoError::Undecodable { format: format_name.clone(), message: e.to_string() })?;
+
+    match format {
+        ImageFormat::Jpeg => Ok(("jpg", bytes.to_vec())),
+        ImageFormat::Png => Ok(("png", bytes.to_vec())),
+        _ => {
+            let mut converted = Cursor::new(Vec::new());
+            DynamicImage::ImageRgb8(decoded.to_rgb8())
+                .write_to(&mut converted, ImageOutputFormat::Jpeg(90))
+                .map_err(|e| PhotoError::Conversion { format: format_name, message: e.to_string() })?;
+            Ok(("jpg", converted.into_inner()))
+        },
+    }
+}
+
+#[derive(Debug)]
+pub struct PhotoDownloads {
+    // Storage keys of photos that still need classifying: new downloads and any
+    // left unclassified by an earlier run
+    pub saved: Vec<String>,
+    pub failures: Vec<PhotoFailure>,
+    // Photos scored by an earlier scan and not downloaded again
+    pub already_scored: usize,
+}
 
 pub struct GooglePlacesClient {
     cred_json_path: String,
     api_key: String,
     base_url: String,
-    output_dir: String,
+    // OAuth endpoint the service account's JWT is exchanged at, also the JWT audience
+    token_url: String,
+    // Where downloaded photos and venue manifests are kept
+    storage: Arc<dyn PhotoStorage>,
+    retry: RetryPolicy,
+    costs: Arc<CostTracker>,
+    // Shared by every request from this client; the lock also makes concurrent
+    // callers wait for one refresh instead of each exchanging their own JWT
+    token: Mutex<Option<AccessToken>>,
+    http: reqwest::Client,
+    downloads: DownloadSettings,
+    // Bounds photo downloads across every venue using this client
+    photo_slots: Semaphore,
+    rate_limiter: RateLimiter,
+}
+
+struct AccessToken {
+    token: String,
+    expires_at: u64,
 }
 
 #[derive(Debug, Deserialize)]
@@ -17,6 +176,13 @@ struct ServiceAccountCredentials {
     //project_id: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    // Seconds from now, normally 3600
+    expires_in: Option<u64>,
+}
+
 #[derive(Debug, Serialize)]
 struct JWTClaims {
     iss: String,  // client_email from service account
@@ -26,9 +192,40 @@ struct JWTClaims {
     scope: String, // Required scopes
 }
 
+// Who took a photo. Google's terms require showing this next to the photo.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorAttribution {
+    #[serde(default)]
+    pub display_name: String,
+    // The author's Google Maps profile
+    #[serde(default)]
+    pub uri: Option<String>,
+    #[serde(default)]
+    pub photo_uri: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct PhotoDetails {
     pub name: String,
+    #[serde(rename = "widthPx")]
+    pub width_px: Option<u32>,
+    #[serde(rename = "heightPx")]
+    pub height_px: Option<u32>,
+    #[serde(rename = "authorAttributions", default)]
+    pub author_attributions: Vec<AuthorAttribution>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LocalizedText {
+    #[serde(default)]
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Review {
+    // Missing for rating-only reviews
+    pub text: Option<LocalizedText>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -37,6 +234,21 @@ pub struct PlaceDetails {
     pub photos: Vec<PhotoDetails>,
     #[serde(rename = "displayName")]
     pub display_name: DisplayName,
+    // Google returns up to 5 of the most relevant reviews
+    #[serde(default)]
+    pub reviews: Vec<Review>,
+    #[serde(rename = "editorialSummary")]
+    pub editorial_summary: Option<LocalizedText>,
+}
+
+impl PlaceDetails {
+    // Every piece of free text about the place: the editorial summary, then the reviews
+    pub fn texts(&self) -> impl Iterator<Item = &str> {
+        self.editorial_summary
+            .iter()
+            .chain(self.reviews.iter().filter_map(|r| r.text.as_ref()))
+            .map(|t| t.text.as_str())
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -44,34 +256,58 @@ pub struct DisplayName {
     pub text: String,
 }
 
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
 impl GooglePlacesClient {
-    pub fn new(cred_json_path: &str, api_key: &str, output_dir: &str) -> Self {
-        // Don't panic if directory already exists
-        if let Err(e) = std::fs::create_dir_all(output_dir) {
-            if e.kind() != std::io::ErrorKind::AlreadyExists {
-                // Only panic for errors other than AlreadyExists
-                panic!("Failed to create output directory: {}", e);
-            }
-        }
-        
+    pub fn new(cred_json_path: &str, api_key: &str, storage: Arc<dyn PhotoStorage>, retry: RetryPolicy, costs: Arc<CostTracker>, downloads: DownloadSettings, places_api: &GooglePlacesSettings) -> Self {
         Self {
             cred_json_path: cred_json_path.to_string(),
             api_key: api_key.to_string(),
-            base_url: "https://places.googleapis.com/v1/".to_string(),
-            output_dir: output_dir.to_string(),
+            base_url: places_api.base_url.clone(),
+            token_url: places_api.token_url.clone(),
+            storage,
+            retry,
+            costs,
+            token: Mutex::new(None),
+            http: reqwest::Client::new(),
+            photo_slots: Semaphore::new(downloads.concurrent_photos.max(1)),
+            rate_limiter: RateLimiter::new(downloads.max_requests_per_second),
+            downloads,
         }
     }
 
-    async fn get_access_token(&self) -> Result<String, Box<dyn std::error::Error>> {
+    // A cached OAuth access token, exchanged for a fresh one shortly before it expires
+    async fn get_access_token(&self) -> Result<String, GoogleApiError> {
+        let mut cached = self.token.lock().await;
+        let now = unix_now();
+        if let Some(token) = cached.as_ref() {
+            if now + TOKEN_REFRESH_MARGIN_SECS < token.expires_at {
+                return Ok(token.token.clone());
+            }
+        }
+
+        let token = self.exchange_token().await?;
+        let access_token = token.token.clone();
+        *cached = Some(token);
+        Ok(access_token)
+    }
+
+    async fn exchange_token(&self) -> Result<AccessToken, GoogleApiError> {
+        let auth_error = |message: String| GoogleApiError::Auth { message };
+
         // Read and parse service account JSON
-        let creds_content = std::fs::read_to_string(&self.cred_json_path)?;
-        let creds: ServiceAccountCredentials = serde_json::from_str(&creds_content)?;
+        let creds_content = std::fs::read_to_string(&self.cred_json_path)
+            .map_err(|e| auth_error(format!("could not read {}: {}", self.cred_json_path, e)))?;
+        let creds: ServiceAccountCredentials = serde_json::from_str(&creds_content)
+            .map_err(|e| auth_error(format!("{} is not a service account key: {}", self.cred_json_path, e)))?;
 
         // Create JWT claims
-        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let now = unix_now();
         let claims = JWTClaims {
             iss: creds.client_email,
-            aud: "https://oauth2.googleapis.com/token".to_string(),
+            aud: self.token_url.clone(),
             exp: now + 3600,
             iat: now,
             scope: "https://www.googleapis.com/auth/maps-platform.places".to_string(),
@@ -79,128 +315,229 @@ impl GooglePlacesClient {
 
         // Create JWT
         let header = Header::new(jsonwebtoken::Algorithm::RS256);
-        let key = EncodingKey::from_rsa_pem(creds.private_key.as_bytes())?;
-        let jwt = encode(&header, &claims, &key)?;
+        let key = EncodingKey::from_rsa_pem(creds.private_key.as_bytes())
+            .map_err(|e| auth_error(format!("invalid private key in {}: {}", self.cred_json_path, e)))?;
+        let jwt = encode(&header, &claims, &key)
+            .map_err(|e| auth_error(format!("could not sign JWT: {}", e)))?;
 
-        // Exchange JWT for access token
-        let client = reqwest::Client::new();
-        let token_response = client
-            .post("https://oauth2.googleapis.com/token")
+        // Exchange JWT for access token. Outages and rate limits are retried like any
+        // other request; anything else means Google refused the credentials.
+        let response = send_with_retry(&self.retry, || self.http
+            .post(&self.token_url)
             .form(&[
                 ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
                 ("assertion", &jwt),
             ])
-            .send()
-            .await?
-            .json::<serde_json::Value>()
-            .await?;
+        ).await.map_err(|e| match e {
+            GoogleApiError::Transient { .. } | GoogleApiError::Quota { .. } => e,
+            other => auth_error(format!("token exchange refused: {}", other)),
+        })?;
 
-        Ok(token_response["access_token"]
-            .as_str()
-            .ok_or("No access token in response")?
-            .to_string())
+        let token_response: TokenResponse = parse_json(response).await
+            .map_err(|e| auth_error(format!("no access token in response: {}", e)))?;
+
+        Ok(AccessToken {
+            token: token_response.access_token,
+            expires_at: now + token_response.expires_in.unwrap_or(claims.exp - now),
+        })
     }
 
     pub async fn get_place_details(&self, place_id: &str) -> Result<PlaceDetails, Box<dyn std::error::Error>> {
         let access_token = self.get_access_token().await?;
-        let place_url = format!("{}places/{}", self.base_url, place_id);
+        let place_url = places_url(&self.base_url, &format!("places/{}", place_id));
 
-        let client = reqwest::Client::new();
-        let response = client
+        self.rate_limiter.wait().await;
+        let response = send_with_retry(&self.retry, || self.http
             .get(&place_url)
             .header("Authorization", format!("Bearer {}", access_token))
-            .header("X-Goog-FieldMask", "photos,displayName")
-            .send()
-            .await?
-            .json()
-            .await?;
-
-        Ok(response)
-    }
-
-    fn cleanup_empty_directory(&self, dir_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let path = std::path::Path::new(dir_path);
-        
-        // Check if directory exists
-        if !path.exists() {
-            return Ok(());
-        }
-
-        // Count jpg files in directory
-        let jpg_count = std::fs::read_dir(path)?
-            .filter_map(Result::ok)
-            .filter(|entry| {
-                entry.path()
-                    .extension()
-                    .and_then(|ext| ext.to_str())
-                    .map(|ext| ext.to_lowercase() == "jpg")
-                    .unwrap_or(false)
-            })
-            .count();
-
-        // If no jpg files found, remove the directory
-        if jpg_count == 0 {
-            println!("No photos found in {}, removing directory", dir_path);
-            std::fs::remove_dir_all(path)?;
-        }
+            .header("X-Goog-FieldMask", "photos,displayName,reviews,editorialSummary")
+        ).await?;
+        self.costs.record(Sku::PlaceDetails);
 
-        Ok(())
+        Ok(parse_json(response).await?)
     }
 
-    pub async fn download_photo(&self, photo_name: &str, place_id: &str, index: usize, venue_name: &str) -> Result<String, Box<dyn std::error::Error>> {
+    pub async fn download_photo(&self, photo_name: &str, place_id: &str, index: usize) -> Result<String, Box<dyn std::error::Error>> {
+        let _slot = self.photo_slots.acquire().await?;
         let access_token = self.get_access_token().await?;
         let photo_url = format!(
-            "{}{}/media?key={}&maxHeightPx=4032&maxWidthPx=4032",
-            self.base_url, photo_name, self.api_key
+            "{}/media?key={}&maxHeightPx=4032&maxWidthPx=4032",
+            places_url(&self.base_url, photo_name), self.api_key
         );
 
-        let client = reqwest::Client::new();
-        let response = client
+        self.rate_limiter.wait().await;
+        // Non-success statuses come back as errors from send_with_retry
+        let response = send_with_retry(&self.retry, || self.http
             .get(&photo_url)
             .header("Authorization", format!("Bearer {}", access_token))
-            .send()
-            .await?;
+        ).await?;
+        self.costs.record(Sku::PlacePhoto);
 
-        // Create a directory for the venue using the venue name
-        let place_dir = format!("{}/{}", self.output_dir, venue_name);
-        std::fs::create_dir_all(&place_dir)?;  // Create the directory if it doesn't exist
-
-        let save_name = format!("{}_{}.jpg", place_id, index);
-        let file_path = format!("{}/{}", place_dir, save_name);  // Use the venue directory for saving
-        println!("Attempting to save photo to: {}", file_path);
+        let content_type = response.headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        let expected_len = response.content_length();
         let bytes = response.bytes().await?;
-        fs::write(&file_path, &bytes)?;
+        // Decoding a full-size photo is too slow for the async workers
+        let (extension, bytes) = tokio::task::spawn_blocking(move || {
+            validate_photo(content_type.as_deref(), expected_len, &bytes)
+        }).await??;
 
-        // After saving the photo, check if download was successful
-        if !std::path::Path::new(&file_path).exists() {
-            // If download failed, cleanup the directory
-            self.cleanup_empty_directory(&place_dir)?;
-        }
+        // Store the photo in the venue's folder, keyed by place id
+        let save_name = format!("{}_{}.{}", place_id, index, extension);
+        let key = venue_key(place_id, &save_name);
+        println!("Saving photo to {} in {}", key, self.storage.describe());
+        self.storage.put(&key, bytes).await?;
 
-        Ok(file_path)
+        Ok(key)
     }
 
-    pub async fn get_place_photos(&self, place_id: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-        let place_data = self.get_place_details(place_id).await?;
-        let mut photo_results = Vec::new();
+    // Download the photos listed in a place's details, a few at a time. Photos the venue's
+    // manifest already has a score for are skipped, so a rescan only pays for new ones.
+    // Photos that fail are listed in `failures` instead of failing the whole venue.
+    pub async fn get_place_photos(&self, place_id: &str, place_data: &PlaceDetails) -> Result<PhotoDownloads, Box<dyn std::error::Error>> {
+        self.get_place_photos_in(place_id, place_data, 0..place_data.photos.len()).await
+    }
 
-        // Use the display name for the directory
-        let venue_name = &place_data.display_name.text;
-        let place_dir = format!("{}/{}", self.output_dir, venue_name);
-        std::fs::create_dir_all(&place_dir)?;  // Create the directory if it doesn't exist
+    // The same for only some of the listed photos, by their position in the list
+    pub async fn get_place_photos_in(&self, place_id: &str, place_data: &PlaceDetails, positions: Range<usize>) -> Result<PhotoDownloads, Box<dyn std::error::Error>> {
+        let stored = stored_venue_keys(self.storage.as_ref(), place_id).await?;
+        let mut manifest = PhotoManifest::load(self.storage.as_ref(), place_id).await?
+            .unwrap_or_else(|| PhotoManifest::new(place_id, &place_data.display_name.text));
+        manifest.venue_name = place_data.display_name.text.clone();
 
+        let mut downloads = PhotoDownloads { saved: Vec::new(), failures: Vec::new(), already_scored: 0 };
+        let mut next_index = manifest.next_file_index();
+        let mut new_photos = Vec::new();
         for (i, photo) in place_data.photos.iter().enumerate() {
-            if let Ok(downloaded_path) = self.download_photo(&photo.name, place_id, i, venue_name).await {
-                photo_results.push(downloaded_path);
+            if !positions.contains(&i) {
+                continue;
+            }
+            // Attributions can change between scans, keep the latest
+            manifest.update_details(photo);
+            match manifest.find(&photo.name) {
+                Some(known) if known.score.is_some() => downloads.already_scored += 1,
+                Some(known) if stored.contains(&venue_key(place_id, &known.file)) =>
+                    downloads.saved.push(venue_key(place_id, &known.file)),
+                _ => {
+                    new_photos.push((i, photo, next_index));
+                    next_index += 1;
+                },
             }
         }
 
-        // After downloading all photos, check if any were successful
-        if photo_results.is_empty() {
-            self.cleanup_empty_directory(&place_dir)?;
+        let photo_downloads: Vec<_> = new_photos
+            .into_iter()
+            .map(|(i, photo, file_index)| async move {
+                let result = self.download_photo(&photo.name, place_id, file_index).await
+                    .map_err(|e| e.to_string());
+                (i, result)
+            })
+            .collect();
+        let mut results: Vec<(usize, Result<String, String>)> = futures::stream::iter(photo_downloads)
+            .buffer_unordered(self.downloads.concurrent_photos_per_venue.max(1))
+            .collect()
+            .await;
+        results.sort_by_key(|(index, _)| *index);
+
+        for (index, result) in results {
+            match result {
+                Ok(key) => {
+                    if let Some((_, file)) = key.rsplit_once('/') {
+                        manifest.record_download(&place_data.photos[index], file.to_string());
+                    }
+                    downloads.saved.push(key);
+                },
+                Err(error) => downloads.failures.push(PhotoFailure {
+                    index,
+                    photo_name: place_data.photos[index].name.clone(),
+                    error,
+                }),
+            }
         }
 
-        println!("Download complete! Successfully downloaded {} place photos", photo_results.len());
-        Ok(photo_results)
+        // Venues without a single photo get no folder at all
+        if !manifest.photos.is_empty() {
+            manifest.save(self.storage.as_ref()).await?;
+        }
+
+        println!("Download complete! {} of {} place photos to classify, {} already scored",
+            downloads.saved.len(), positions.len(), downloads.already_scored);
+        Ok(downloads)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jpeg() -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        image::RgbImage::from_pixel(32, 24, image::Rgb([200, 30, 60]))
+            .write_to(&mut bytes, ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn html_and_json_bodies_are_not_images() {
+        let html = b"<html><body>Quota exceeded</body></html>";
+        match validate_photo(Some("text/html; charset=utf-8"), None, html) {
+            Err(PhotoError::NotAnImage { content_type, body }) => {
+                assert_eq!(content_type, "text/html; charset=utf-8");
+                assert_eq!(body, "<html><body>Quota exceeded</body></html>");
+            },
+            other => panic!("expected NotAnImage, got {:?}", other),
+        }
+
+        let json = format!(r#"{{"error": {{"message": "{}"}}}}"#, "x".repeat(500));
+        match validate_photo(Some("application/json"), None, json.as_bytes()) {
+            Err(PhotoError::NotAnImage { body, .. }) => assert_eq!(body.chars().count(), MAX_ERROR_BODY_CHARS),
+            other => panic!("expected NotAnImage, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_short_download_is_truncated() {
+        let full = jpeg();
+        let cut = &full[..full.len() / 2];
+        match validate_photo(Some("image/jpeg"), Some(full.len() as u64), cut) {
+            Err(PhotoError::Truncated { expected, received }) => {
+                assert_eq!(expected, full.len() as u64);
+                assert_eq!(received, cut.len() as u64);
+            },
+            other => panic!("expected Truncated, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn garbage_after_a_magic_number_is_undecodable() {
+        let mut fake_jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0];
+        fake_jpeg.extend(b"not really a jpeg at all");
+        match validate_photo(Some("image/jpeg"), Some(fake_jpeg.len() as u64), &fake_jpeg) {
+            Err(PhotoError::Undecodable { format, .. }) => assert_eq!(format, "Jpeg"),
+            other => panic!("expected Undecodable, got {:?}", other),
+        }
+
+        let mut fake_png = b"\x89PNG\r\n\x1a\n".to_vec();
+        fake_png.extend([0u8; 32]);
+        assert!(matches!(validate_photo(None, None, &fake_png), Err(PhotoError::Undecodable { .. })));
+        assert!(matches!(validate_photo(None, None, b"plain old bytes"), Err(PhotoError::UnknownFormat)));
+    }
+
+    #[test]
+    fn jpeg_is_kept_and_webp_converted_to_jpeg() {
+        let original = jpeg();
+        let (extension, bytes) = validate_photo(Some("image/jpeg"), Some(original.len() as u64), &original).unwrap();
+        assert_eq!(extension, "jpg");
+        assert_eq!(bytes, original);
+
+        // A lossless WebP from the image crate's own test images
+        let webp = include_bytes!("../tests/fixtures/two_colour.webp");
+        let (extension, bytes) = validate_photo(Some("image/webp"), Some(webp.len() as u64), webp).unwrap();
+        assert_eq!(extension, "jpg");
+        assert_eq!(image::guess_format(&bytes).unwrap(), ImageFormat::Jpeg);
+        let (webp_image, converted) = (image::load_from_memory(webp).unwrap(), image::load_from_memory(&bytes).unwrap());
+        assert_eq!((converted.width(), converted.height()), (webp_image.width(), webp_image.height()));
+    }
+}
@@ -1,10 +1,59 @@
 use dotenv::dotenv;
-use serde::Deserialize;
-use reqwest;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use crate::Coverage;
+use crate::cost::{CostTracker, Sku};
+use crate::geometry::SearchCircle;
+use crate::google_api::{is_fatal_error, parse_json, send_with_retry, RetryPolicy};
+
+// searchNearby never returns more than this many places for one request
+pub const NEARBY_RESULT_CAP: usize = 20;
+
+// searchText pages hold 20 places and Google stops handing out tokens after 3 pages
+const TEXT_SEARCH_MAX_PAGES: usize = 3;
+
+// Always requested, the pipeline needs these to place and name a venue
+const REQUIRED_PLACE_FIELDS: [&str; 4] = ["id", "displayName", "location", "formattedAddress"];
+
+// Extra place fields requested by nearby and text search unless `place_fields` says otherwise.
+// websiteUri, nationalPhoneNumber, rating, priceLevel and regularOpeningHours move the
+// searches to Google's Enterprise SKU.
+pub fn default_place_fields() -> Vec<String> {
+    ["types", "businessStatus", "websiteUri", "nationalPhoneNumber", "rating", "priceLevel", "regularOpeningHours"]
+        .iter()
+        .map(|f| f.to_string())
+        .collect()
+}
+
+// The X-Goog-FieldMask for a search: the required fields plus `extra`, each under `places.`
+pub fn search_field_mask(extra: &[String]) -> String {
+    let mut fields: Vec<&str> = REQUIRED_PLACE_FIELDS.to_vec();
+    for field in extra {
+        if !fields.contains(&field.as_str()) {
+            fields.push(field);
+        }
+    }
+    fields.iter().map(|f| format!("places.{}", f)).collect::<Vec<_>>().join(",")
+}
+
+// A Places API method or resource under `base_url`, with or without its trailing slash
+pub fn places_url(base_url: &str, path: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), path)
+}
+
+// What every Places search in a run shares
+pub struct PlacesSearch<'a> {
+    pub api_key: &'a str,
+    // providers.google_places.base_url
+    pub base_url: &'a str,
+    pub retry: &'a RetryPolicy,
+    pub costs: &'a CostTracker,
+    pub field_mask: String,
+}
 
 // Struct definitions
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Location {
     pub latitude: f64,
     pub longitude: f64,
@@ -27,42 +76,136 @@ impl std::fmt::Display for Location {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DisplayName {
     pub text: String
 }
 
-#[derive(Debug, Deserialize)]
+// A point in the week, day 0 is Sunday
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpeningTime {
+    #[serde(default)]
+    pub day: u8,
+    #[serde(default)]
+    pub hour: u8,
+    #[serde(default)]
+    pub minute: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpeningPeriod {
+    pub open: OpeningTime,
+    // Missing for places open around the clock
+    #[serde(default)]
+    pub close: Option<OpeningTime>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpeningHours {
+    #[serde(default)]
+    pub periods: Vec<OpeningPeriod>,
+    // Human readable, e.g. "Friday: 4:00 PM – 2:00 AM"
+    #[serde(default)]
+    pub weekday_descriptions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Place {
     pub id: String, // Place ID
     #[serde(rename = "displayName")] // Map JSON field "displayName" to Rust field "display_name"
     pub display_name: DisplayName, // Nested display name object
-    #[allow(dead_code)]  // Add this attribute to suppress the warning
     pub location: Location, // Nested location object
     #[serde(rename = "formattedAddress")]
     pub formatted_address: String,
+    // Optional metadata, only present when asked for in the field mask
+    #[serde(default)]
+    pub types: Vec<String>,
+    #[serde(rename = "businessStatus", default)]
+    pub business_status: Option<String>,
+    #[serde(rename = "websiteUri", default)]
+    pub website_uri: Option<String>,
+    #[serde(rename = "nationalPhoneNumber", default)]
+    pub national_phone_number: Option<String>,
+    #[serde(default)]
+    pub rating: Option<f64>,
+    // e.g. "PRICE_LEVEL_MODERATE"
+    #[serde(rename = "priceLevel", default)]
+    pub price_level: Option<String>,
+    #[serde(rename = "regularOpeningHours", default)]
+    pub regular_opening_hours: Option<OpeningHours>,
+    // Which queries returned this place, e.g. "type:bar" or "text:billiards".
+    // Never sent by Google, only kept in the discovery cache.
+    #[serde(default)]
+    pub found_by: Vec<String>,
+}
+
+impl Place {
+    pub fn is_permanently_closed(&self) -> bool {
+        self.business_status.as_deref() == Some("CLOSED_PERMANENTLY")
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct PlacesResponse {
+    // Google leaves the key out entirely when nothing matched
+    #[serde(default)]
     pub places: Vec<Place>,
 }
 
+impl PlacesResponse {
+    // Append places, folding duplicates (same place id) into the copy we already hold
+    pub fn merge(&mut self, other: PlacesResponse) {
+        let mut seen: HashMap<String, usize> = self.places
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p.id.clone(), i))
+            .collect();
+        for place in other.places {
+            match seen.get(&place.id) {
+                Some(&i) => {
+                    for query in place.found_by {
+                        if !self.places[i].found_by.contains(&query) {
+                            self.places[i].found_by.push(query);
+                        }
+                    }
+                }
+                None => {
+                    seen.insert(place.id.clone(), self.places.len());
+                    self.places.push(place);
+                }
+            }
+        }
+    }
+
+    fn tag_found_by(&mut self, query: &str) {
+        for place in &mut self.places {
+            place.found_by = vec![query.to_string()];
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TextSearchResponse {
+    #[serde(default)]
+    places: Vec<Place>,
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+}
+
 // Get the Places in the Local Geographic Region
-pub async fn search_places(api_key: &str, lat: f64, lon: f64, radius: f64, place_type: &str) -> Result<PlacesResponse, Box<dyn std::error::Error>> {
+pub async fn search_places(search: &PlacesSearch<'_>, lat: f64, lon: f64, radius: f64, place_type: &str) -> Result<PlacesResponse, Box<dyn std::error::Error>> {
     dotenv().ok();
     //let api_key = env::var("GOOGLE_PLACES_API_KEY")?;
     
-    let url = format!(
-        "https://places.googleapis.com/v1/places:searchNearby",
-        // lat, lon, radius will go in the request body
-    );
+    // lat, lon, radius go in the request body
+    let url = places_url(search.base_url, "places:searchNearby");
 
     let client = reqwest::Client::new();
-    let response = client
+    let response = send_with_retry(search.retry, || client
         .post(&url)
-        .header("X-Goog-Api-Key", api_key)
-        .header("X-Goog-FieldMask", "places.id,places.displayName,places.location,places.formattedAddress")
+        .header("X-Goog-Api-Key", search.api_key)
+        .header("X-Goog-FieldMask", &search.field_mask)
         .json(&serde_json::json!({
             "locationRestriction": {
                 "circle": {
@@ -73,15 +216,14 @@ pub async fn search_places(api_key: &str, lat: f64, lon: f64, radius: f64, place
                     "radius": radius
                 }
             },
-            "includedTypes": [place_type]
+            "includedTypes": [place_type],
+            "maxResultCount": NEARBY_RESULT_CAP
         }))
-        .send()
-        .await?;
-
-    let result = response.text().await?;
-    // println!("API Response: {}", result); // Print the raw API response for debugging
+    ).await?;
+    search.costs.record(Sku::NearbySearch);
 
-    let places_response: PlacesResponse = serde_json::from_str(&result)?;
+    let mut places_response: PlacesResponse = parse_json(response).await?;
+    places_response.tag_found_by(&format!("type:{}", place_type));
     
     //for place in &places_response.places {
     //    println!("ID: {}", place.id); // Print the place ID
@@ -91,4 +233,272 @@ pub async fn search_places(api_key: &str, lat: f64, lon: f64, radius: f64, place
     //}
 
     Ok(places_response)
-}
\ No newline at end of file
+}
+
+// Cover a large circle with searchNearby requests.
+// A single request is capped at NEARBY_RESULT_CAP places, so any cell that comes back
+// full is split into smaller overlapping cells and searched again, down to
+// `coverage.min_cell_radius_meters` or `coverage.max_depth` levels.
+// Places outside the original circle (picked up by the overlapping cells) are dropped.
+pub async fn search_places_tiled(
+    search: &PlacesSearch<'_>,
+    area: SearchCircle,
+    place_type: &str,
+    coverage: &Coverage,
+) -> Result<PlacesResponse, Box<dyn std::error::Error>> {
+    let mut all_places = PlacesResponse { places: Vec::new() };
+    let mut cells = vec![(area, 0u32)];
+    let mut requests = 0;
+
+    while let Some((cell, depth)) = cells.pop() {
+        // Deep subdivision is where the money goes, so stop splitting once the budget is spent
+        if search.costs.budget_exhausted() {
+            println!("Budget reached, {} {} cells left unsearched", cells.len() + 1, place_type);
+            break;
+        }
+        requests += 1;
+        let response = match search_places(search, cell.latitude, cell.longitude, cell.radius_meters, place_type).await {
+            Ok(response) => response,
+            // The whole area failing is an error; a single sub-cell failing is not,
+            // unless it failed on something every other cell will hit as well
+            Err(e) if depth == 0 || is_fatal_error(e.as_ref()) => return Err(e),
+            Err(e) => {
+                eprintln!("Error searching {} cell at ({}, {}): {}", place_type, cell.latitude, cell.longitude, e);
+                continue;
+            }
+        };
+
+        if response.places.len() >= NEARBY_RESULT_CAP {
+            let children = cell.subdivide(coverage.cell_overlap);
+            let can_split = depth < coverage.max_depth
+                && children[0].radius_meters >= coverage.min_cell_radius_meters;
+            if can_split {
+                cells.extend(children.into_iter().map(|child| (child, depth + 1)));
+            } else {
+                println!("Warning: {} cell at ({}, {}) r={:.0}m is still full, some places may be missing",
+                    place_type, cell.latitude, cell.longitude, cell.radius_meters);
+            }
+        }
+
+        all_places.merge(response);
+    }
+
+    all_places.places.retain(|p| area.contains(p.location.latitude, p.location.longitude));
+    println!("Tiled search for {} used {} requests, found {} places", place_type, requests, all_places.places.len());
+    Ok(all_places)
+}
+
+// Free-text search ("billiards", "pool hall", ...) biased towards the search area.
+// Google treats the circle as a preference only, so results outside it are dropped here.
+pub async fn search_text(search: &PlacesSearch<'_>, area: SearchCircle, query: &str) -> Result<PlacesResponse, Box<dyn std::error::Error>> {
+    let url = places_url(search.base_url, "places:searchText");
+    let client = reqwest::Client::new();
+
+    let mut all_places = PlacesResponse { places: Vec::new() };
+    let mut page_token: Option<String> = None;
+
+    for _ in 0..TEXT_SEARCH_MAX_PAGES {
+        let mut body = serde_json::json!({
+            "textQuery": query,
+            "locationBias": {
+                "circle": {
+                    "center": {
+                        "latitude": area.latitude,
+                        "longitude": area.longitude
+                    },
+                    "radius": area.radius_meters
+                }
+            },
+            "pageSize": NEARBY_RESULT_CAP
+        });
+        if let Some(token) = &page_token {
+            body["pageToken"] = serde_json::json!(token);
+        }
+
+        let response = send_with_retry(search.retry, || client
+            .post(&url)
+            .header("X-Goog-Api-Key", search.api_key)
+            .header("X-Goog-FieldMask", format!("{},nextPageToken", search.field_mask))
+            .json(&body)
+        ).await?;
+        search.costs.record(Sku::TextSearch);
+
+        let page: TextSearchResponse = parse_json(response).await?;
+
+        let mut places = PlacesResponse { places: page.places };
+        places.tag_found_by(&format!("text:{}", query));
+        all_places.merge(places);
+
+        match page.next_page_token {
+            Some(token) if !search.costs.budget_exhausted() => page_token = Some(token),
+            _ => break,
+        }
+    }
+
+    all_places.places.retain(|p| area.contains(p.location.latitude, p.location.longitude));
+    Ok(all_places)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cost::PriceTable;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn stub_place(id: &str, latitude: f64, longitude: f64) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "displayName": { "text": id },
+            "location": { "latitude": latitude, "longitude": longitude },
+            "formattedAddress": "1 Main Street",
+        })
+    }
+
+    // A searchNearby that is always full: 18 places at the cell's centre and 2 far
+    // outside it, each id unique to the cell
+    async fn full_nearby(body: web::Json<serde_json::Value>, requests: web::Data<AtomicUsize>) -> HttpResponse {
+        requests.fetch_add(1, Ordering::SeqCst);
+        let center = &body["locationRestriction"]["circle"]["center"];
+        let (lat, lon) = (center["latitude"].as_f64().unwrap(), center["longitude"].as_f64().unwrap());
+        let places: Vec<serde_json::Value> = (0..NEARBY_RESULT_CAP)
+            .map(|i| {
+                let id = format!("{:.6},{:.6}#{}", lat, lon, i);
+                if i < 2 { stub_place(&id, lat + 1.0, lon) } else { stub_place(&id, lat, lon) }
+            })
+            .collect();
+        HttpResponse::Ok().json(serde_json::json!({ "places": places }))
+    }
+
+    async fn start_nearby_stub() -> (String, web::Data<AtomicUsize>) {
+        let requests = web::Data::new(AtomicUsize::new(0));
+        let data = requests.clone();
+        let server = HttpServer::new(move || App::new()
+            .app_data(data.clone())
+            .route("/v1/places:searchNearby", web::post().to(full_nearby)))
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let base_url = format!("http://{}/v1/", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        (base_url, requests)
+    }
+
+    fn coverage(max_depth: u32, min_cell_radius_meters: f64) -> Coverage {
+        Coverage { tiled: true, max_depth, min_cell_radius_meters, ..Coverage::default() }
+    }
+
+    async fn tiled_search(base_url: &str, coverage: &Coverage) -> PlacesResponse {
+        let retry = RetryPolicy::default();
+        let costs = CostTracker::new(PriceTable::default(), None);
+        let search = PlacesSearch { api_key: "key", base_url, retry: &retry, costs: &costs, field_mask: search_field_mask(&[]) };
+        search_places_tiled(&search, SearchCircle::new(40.0, -75.0, 1000.0), "bar", coverage).await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn full_cells_are_split_and_outside_places_dropped() {
+        let (base_url, requests) = start_nearby_stub().await;
+        let places = tiled_search(&base_url, &coverage(1, 1.0)).await.places;
+
+        // The area and its four children, 18 places inside from each
+        assert_eq!(requests.load(Ordering::SeqCst), 5);
+        assert_eq!(places.len(), 5 * 18);
+        let area = SearchCircle::new(40.0, -75.0, 1000.0);
+        assert!(places.iter().all(|p| area.contains(p.location.latitude, p.location.longitude)));
+        assert!(places.iter().all(|p| p.found_by == vec!["type:bar".to_string()]));
+    }
+
+    #[actix_web::test]
+    async fn splitting_stops_at_max_depth() {
+        let (base_url, requests) = start_nearby_stub().await;
+        tiled_search(&base_url, &coverage(0, 1.0)).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let (base_url, requests) = start_nearby_stub().await;
+        tiled_search(&base_url, &coverage(2, 1.0)).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 1 + 4 + 16);
+    }
+
+    #[actix_web::test]
+    async fn splitting_stops_at_the_minimum_cell_radius() {
+        // Children of a 1000 m cell are 778 m, grandchildren 605 m
+        let (base_url, requests) = start_nearby_stub().await;
+        tiled_search(&base_url, &coverage(5, 800.0)).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let (base_url, requests) = start_nearby_stub().await;
+        tiled_search(&base_url, &coverage(5, 700.0)).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 5);
+    }
+
+    // Text results that never run out of pages; the page token names the next page
+    async fn paged_text(request: actix_web::HttpRequest, body: web::Json<serde_json::Value>, requests: web::Data<AtomicUsize>) -> HttpResponse {
+        requests.fetch_add(1, Ordering::SeqCst);
+        let mask = request.headers().get("X-Goog-FieldMask").and_then(|v| v.to_str().ok()).unwrap_or("");
+        if !mask.ends_with(",nextPageToken") || body["textQuery"] != "billiards" {
+            return HttpResponse::BadRequest().finish();
+        }
+        let page: usize = body["pageToken"].as_str().map(|t| t.trim_start_matches('p').parse().unwrap()).unwrap_or(1);
+        let mut places = vec![stub_place(&format!("page{}", page), 40.0, -75.0)];
+        match page {
+            // Shows up again on page 2
+            1 => places.push(stub_place("regular", 40.001, -75.0)),
+            2 => places.push(stub_place("regular", 40.001, -75.0)),
+            // Google's bias is only a preference; this one is far outside the area
+            3 => places.push(stub_place("far_away", 41.0, -75.0)),
+            _ => {}
+        }
+        HttpResponse::Ok().json(serde_json::json!({ "places": places, "nextPageToken": format!("p{}", page + 1) }))
+    }
+
+    async fn text_search(budget: Option<f64>) -> (PlacesResponse, usize) {
+        let requests = web::Data::new(AtomicUsize::new(0));
+        let data = requests.clone();
+        let server = HttpServer::new(move || App::new()
+            .app_data(data.clone())
+            .route("/v1/places:searchText", web::post().to(paged_text)))
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let base_url = format!("http://{}/v1", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+
+        let retry = RetryPolicy::default();
+        let costs = CostTracker::new(PriceTable::default(), budget);
+        let search = PlacesSearch { api_key: "key", base_url: &base_url, retry: &retry, costs: &costs, field_mask: search_field_mask(&[]) };
+        let places = search_text(&search, SearchCircle::new(40.0, -75.0, 1000.0), "billiards").await.unwrap();
+        (places, requests.load(Ordering::SeqCst))
+    }
+
+    #[actix_web::test]
+    async fn text_search_follows_page_tokens_up_to_the_limit() {
+        let (response, requests) = text_search(None).await;
+        assert_eq!(requests, TEXT_SEARCH_MAX_PAGES);
+
+        let ids: Vec<&str> = response.places.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, vec!["page1", "regular", "page2", "page3"]);
+        assert!(response.places.iter().all(|p| p.found_by == vec!["text:billiards".to_string()]));
+    }
+
+    #[actix_web::test]
+    async fn text_search_stops_paging_once_the_budget_is_spent() {
+        let (response, requests) = text_search(Some(0.0001)).await;
+        assert_eq!(requests, 1);
+        assert_eq!(response.places.len(), 2);
+    }
+
+    #[test]
+    fn merge_keeps_one_copy_and_every_query() {
+        let place = |id: &str, query: &str| {
+            let mut place: Place = serde_json::from_value(stub_place(id, 40.0, -75.0)).unwrap();
+            place.found_by = vec![query.to_string()];
+            place
+        };
+        let mut all = PlacesResponse { places: vec![place("a", "type:bar"), place("b", "type:bar")] };
+        all.merge(PlacesResponse { places: vec![place("a", "text:billiards"), place("c", "text:billiards")] });
+        let found_by: Vec<(&str, Vec<String>)> = all.places.iter().map(|p| (p.id.as_str(), p.found_by.clone())).collect();
+        assert_eq!(found_by, vec![
+            ("a", vec!["type:bar".to_string(), "text:billiards".to_string()]),
+            ("b", vec!["type:bar".to_string()]),
+            ("c", vec!["text:billiards".to_string()]),
+        ]);
+    }
+}
@@ -1,76 +1,294 @@
 use dotenv::dotenv;
 use std::env;
-use tokio;
-use std::process::Command;
 use std::path::{PathBuf, Path};
 use serde::{Serialize, Deserialize};
 use std::fs::File;
-use serde_yaml;
 use clap::Parser;
 use futures::future::BoxFuture;
+use std::sync::Arc;
+use std::collections::VecDeque;
+use tokio::sync::Semaphore;
+
+mod geometry;
+use geometry::SearchCircle;
+
+mod search_area;
+use search_area::{AreaSpec, SearchArea};
+
+mod cost;
+use cost::{CostTracker, PriceTable};
+
+mod google_api;
+use google_api::{is_fatal_error, RetryPolicy};
 
 mod google_places_search;
-use google_places_search::{search_places, PlacesResponse};  //just import function call
+use google_places_search::{default_place_fields, Place};
+
+mod overpass_search;
+use overpass_search::OSM_ID_PREFIX;
+
+mod place_provider;
+
+mod planning;
+use planning::{discover_places, plan_search, Discovery, PlanningSettings};
+
+mod review_text;
+use review_text::score_texts;
+
+mod score_fusion;
+use score_fusion::{rescore_venue, FusionSettings};
+
+mod geocoding;
+use geocoding::{geocoder_for, GeocodedLocation};
+
+mod photo_manifest;
+use photo_manifest::{migrate_output_dir, venue_key, PhotoManifest, MANIFEST_FILE};
+
+mod storage;
+use storage::{storage_backend, storage_from_env, PhotoStorage};
+
+mod native_classifier;
+
+mod python_worker;
+use python_worker::PythonWorkerSettings;
+
+mod tiled_classifier;
+
+mod classifier;
+use classifier::{classifier_for, Classifier, ClassifierSettings, Inference};
 
 mod google_places_photos_reviews;
-use google_places_photos_reviews::GooglePlacesClient;
+use google_places_photos_reviews::{is_photo_file, DownloadSettings, GooglePlacesClient, PhotoDownloads, PlaceDetails};
 
 mod web_server;
-use web_server::{AppState, start_server};
+use web_server::{AppState, SharedSettings, start_server};
 
 mod models;
 use models::{Venue, VenueCollection};
 
-fn run_python_script(
-    file_path: &PathBuf, 
-    model_path: &str, 
-    output_dir: &PathBuf,
+// The image evidence gathered for one venue
+struct PhotoScan {
+    image_score: f32,
+    // Photos Google listed that early stopping never looked at
+    photos_skipped: usize,
+}
+
+async fn report_photo_failures<F>(photos: &PhotoDownloads, venue_name: &str, status_callback: &Option<F>)
+where
+    F: Fn(&str) -> BoxFuture<'static, ()>,
+{
+    for failure in &photos.failures {
+        let status = format!("Photo {} of {} failed ({}): {}",
+            failure.index + 1, venue_name, failure.photo_name, failure.error);
+        eprintln!("{}", status);
+        if let Some(callback) = status_callback {
+            callback(&status).await;
+        }
+    }
+}
+
+// Classify photos of one venue folder. Classifiers read local files, so photos in
+// remote storage are fetched into the local cache first. Inference blocks for
+// seconds at a time, keep it off the async workers.
+async fn classify_photos(
+    photos: &PhotoDownloads,
+    storage: &dyn PhotoStorage,
+    classifier: &Arc<dyn Classifier>,
     save_negative: bool,
-) -> Result<f32, Box<dyn std::error::Error>> {
-    let output = Command::new("python3")
-        .arg("PoolTableInference.py")
-        .arg("-i")
-        .arg(file_path)
-        .arg("-m")
-        .arg(model_path)
-        .arg("-o")
-        .arg(output_dir)
-        .arg("--save-negative")
-        .arg(save_negative.to_string())
-        .output()?;
-
-    if !output.status.success() {
-        let error = String::from_utf8_lossy(&output.stderr);
-        println!("Error running Python script: {}", error);
-        return Err(error.into());
+) -> Result<Inference, Box<dyn std::error::Error>> {
+    let mut image_paths: Vec<PathBuf> = Vec::new();
+    for key in &photos.saved {
+        image_paths.push(storage.local_path(key).await?);
+    }
+    if image_paths.is_empty() {
+        return Ok(Inference::default());
     }
+    let classifier = classifier.clone();
+    let inference = tokio::task::spawn_blocking(move || {
+        classifier.classify(&image_paths, save_negative)
+            .map_err(|e| e.to_string())
+    }).await??;
+    Ok(inference)
+}
 
-    // Parse the output to get probability
-    let output_str = String::from_utf8_lossy(&output.stdout);
-    for line in output_str.lines() {
-        if line.starts_with("VENUE_PROBABILITY:") {
-            if let Ok(prob) = line.strip_prefix("VENUE_PROBABILITY:").unwrap().trim().parse::<f32>() {
-                return Ok(prob);
+// Store new per-photo results next to the ones from earlier scans; the venue's image
+// score over all of them. The classifier only removes negatives from its local copy, so
+// unless they are kept they are deleted from storage here.
+async fn merge_photo_scores(storage: &dyn PhotoStorage, place_id: &str, inference: &Inference, save_negative: bool) -> f32 {
+    if !save_negative {
+        for (file, result) in &inference.images {
+            if result.pool_table_score() < 0.5 {
+                if let Err(e) = storage.delete(&venue_key(place_id, file)).await {
+                    eprintln!("Error deleting {} from {}: {}", file, storage.describe(), e);
+                }
             }
         }
     }
-    
-    // If we didn't find a probability, return 0.0 as default
-    Ok(0.0)
+    match PhotoManifest::load(storage, place_id).await {
+        Ok(Some(mut manifest)) => {
+            manifest.set_results(&inference.images);
+            if let Err(e) = manifest.save(storage).await {
+                eprintln!("Error saving photo manifest of {}: {}", place_id, e);
+            }
+            manifest.image_score().unwrap_or(inference.probability)
+        },
+        Ok(None) => inference.probability,
+        Err(e) => {
+            eprintln!("Error loading photo manifest of {}: {}", place_id, e);
+            inference.probability
+        },
+    }
+}
+
+// Classify a venue's photos once they are all downloaded. Only the photos without
+// a score go to the classifier.
+async fn scan_downloaded_photos<F>(
+    photos: PhotoDownloads,
+    venue_name: &str,
+    storage: &dyn PhotoStorage,
+    place_id: &str,
+    classifier: &Arc<dyn Classifier>,
+    config: &Config,
+    status_callback: &Option<F>,
+) -> Result<PhotoScan, Box<dyn std::error::Error>>
+where
+    F: Fn(&str) -> BoxFuture<'static, ()>,
+{
+    report_photo_failures(&photos, venue_name, status_callback).await;
+    if photos.already_scored > 0 {
+        if let Some(callback) = status_callback {
+            callback(&format!("Reusing scores of {} photos of {} from an earlier scan",
+                photos.already_scored, venue_name)).await;
+        }
+    }
+
+    let save_negative = config.processing.save_negative_images;
+    let inference = classify_photos(&photos, storage, classifier, save_negative).await?;
+    Ok(PhotoScan {
+        image_score: merge_photo_scores(storage, place_id, &inference, save_negative).await,
+        photos_skipped: 0,
+    })
+}
+
+// Early stopping: download and classify one photo at a time, and stop once a photo
+// scores at least `confidence` or `max_photos_per_venue` photos were looked at
+async fn stream_venue_photos<F>(
+    client: &GooglePlacesClient,
+    place_id: &str,
+    details: &PlaceDetails,
+    storage: &dyn PhotoStorage,
+    classifier: &Arc<dyn Classifier>,
+    config: &Config,
+    status_callback: &Option<F>,
+) -> Result<PhotoScan, Box<dyn std::error::Error>>
+where
+    F: Fn(&str) -> BoxFuture<'static, ()>,
+{
+    let settings = &config.early_stop;
+    let venue_name = &details.display_name.text;
+    let listed = details.photos.len();
+
+    // A photo scored by an earlier scan may already be enough
+    let save_negative = config.processing.save_negative_images;
+    let mut image_score = PhotoManifest::load(storage, place_id).await?.and_then(|m| m.image_score());
+    let mut looked_at = 0;
+    let mut already_scored = 0;
+    // Only photos downloaded and classified in this scan count towards the cap
+    let mut classified = 0;
+    for index in 0..listed {
+        if image_score.unwrap_or(0.0) >= settings.confidence
+            || (settings.max_photos_per_venue > 0 && classified >= settings.max_photos_per_venue)
+        {
+            break;
+        }
+        let photos = client.get_place_photos_in(place_id, details, index..index + 1).await?;
+        report_photo_failures(&photos, venue_name, status_callback).await;
+        already_scored += photos.already_scored;
+        classified += photos.saved.len();
+        looked_at += 1;
+
+        let inference = classify_photos(&photos, storage, classifier, save_negative).await?;
+        image_score = Some(merge_photo_scores(storage, place_id, &inference, save_negative).await);
+    }
+
+    let photos_skipped = listed - looked_at;
+    if let Some(callback) = status_callback {
+        let mut status = format!("Looked at {} of {} photos of {}", looked_at, listed, venue_name);
+        if already_scored > 0 {
+            status.push_str(&format!(", {} scored by an earlier scan", already_scored));
+        }
+        if photos_skipped > 0 {
+            status.push_str(&format!(", stopped early and skipped {}", photos_skipped));
+        }
+        callback(&status).await;
+    }
+    Ok(PhotoScan {
+        image_score: image_score.unwrap_or(0.0),
+        photos_skipped,
+    })
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
-    pub location: Location,
+    // Center and radius of the search; not needed when `area` is given
+    #[serde(default)]
+    pub location: Option<Location>,
+    // A polygon or bbox to search instead of the `location` circle
+    #[serde(default)]
+    pub area: Option<AreaSpec>,
     pub processing: Processing,
     pub place_types: Vec<String>,
+    #[serde(default)]
+    pub coverage: Coverage,
+    // Free-text queries such as "billiards" or "pool hall", searched alongside place_types
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    pub providers: Providers,
+    #[serde(default)]
+    pub geocoding: GeocodingSettings,
+    // Retries for rate-limited (429) and failing (5xx) Google requests
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    // USD per 1000 calls, used to estimate what a run costs
+    #[serde(default)]
+    pub prices: PriceTable,
+    // Stop the run once the estimated cost reaches this many USD
+    #[serde(default)]
+    pub budget: Option<f64>,
+    // Place metadata requested by nearby and text search (types, businessStatus, ...)
+    #[serde(default = "default_place_fields")]
+    pub place_fields: Vec<String>,
+    // How image, review, place-type and human evidence combine into one score
+    #[serde(default)]
+    pub fusion: FusionSettings,
+    // Parallel photo downloads and the request rate they may use
+    #[serde(default)]
+    pub downloads: DownloadSettings,
+    // Discovery cache and dry-run estimates
+    #[serde(default)]
+    pub planning: PlanningSettings,
+    // Classify photos as they download and stop once a venue is clearly positive
+    #[serde(default)]
+    pub early_stop: EarlyStopSettings,
+    // Keep one Python process running for every venue instead of one per venue
+    #[serde(default)]
+    pub python_worker: PythonWorkerSettings,
+    // Which classifier scores the photos: Python, ONNX or a fixture-driven mock
+    #[serde(default)]
+    pub classifier: ClassifierSettings,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Location {
-    pub latitude: f64,
-    pub longitude: f64,
+    // Either coordinates, or an `address` ("Lexington, MA") to geocode
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
     pub radius_meters: f64,
+    #[serde(default)]
+    pub address: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -78,6 +296,169 @@ pub struct Processing {
     pub months_threshold: i64,
     pub reprocess_all: bool,
     pub save_negative_images: bool,
+    // Don't download photos for places Google lists as CLOSED_PERMANENTLY
+    #[serde(default)]
+    pub skip_permanently_closed: bool,
+}
+
+// Streaming photo scan. Off by default, every listed photo is downloaded and classified.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EarlyStopSettings {
+    pub enabled: bool,
+    // Stop once a photo scores at least this; photos left over are never downloaded
+    pub confidence: f32,
+    // Download and classify no more than this many new photos per venue, 0 for no cap
+    pub max_photos_per_venue: usize,
+}
+
+impl Default for EarlyStopSettings {
+    fn default() -> Self {
+        EarlyStopSettings {
+            enabled: false,
+            confidence: 0.9,
+            max_photos_per_venue: 10,
+        }
+    }
+}
+
+impl Config {
+    pub fn search_area(&self) -> Result<SearchArea, Box<dyn std::error::Error>> {
+        match (&self.area, &self.location) {
+            (Some(area), _) => area.resolve(),
+            (None, Some(location)) => match (location.latitude, location.longitude) {
+                (Some(latitude), Some(longitude)) => Ok(SearchArea::Circle(SearchCircle::new(
+                    latitude,
+                    longitude,
+                    location.radius_meters,
+                ))),
+                _ => Err("Location needs latitude and longitude, or an address that has been geocoded".into()),
+            },
+            (None, None) => Err("Config needs either a location or an area to search".into()),
+        }
+    }
+
+    // Fill in the location's coordinates from its address.
+    // Returns what the address resolved to, or None when coordinates were given directly.
+    // Google geocoding calls are counted in `costs`.
+    pub async fn resolve_location(&mut self, api_key: &str, costs: &CostTracker) -> Result<Option<GeocodedLocation>, Box<dyn std::error::Error>> {
+        let location = match &mut self.location {
+            Some(location) if location.latitude.is_none() || location.longitude.is_none() => location,
+            _ => return Ok(None),
+        };
+        let address = location.address.clone().ok_or("Location needs latitude and longitude, or an address")?;
+
+        let geocoded = geocoder_for(&self.geocoding, api_key, &self.retry, costs).geocode(&address).await?;
+        location.latitude = Some(geocoded.latitude);
+        location.longitude = Some(geocoded.longitude);
+        Ok(Some(geocoded))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GeocoderBackend {
+    // Offline lookup in a bundled city list
+    Gazetteer,
+    // Google Geocoding API, needs the Geocoding API enabled on the key
+    Google,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GeocodingSettings {
+    pub backend: GeocoderBackend,
+    pub gazetteer_path: String,
+}
+
+impl Default for GeocodingSettings {
+    fn default() -> Self {
+        GeocodingSettings {
+            backend: GeocoderBackend::Gazetteer,
+            gazetteer_path: "data/gazetteer_cities.tsv".to_string(),
+        }
+    }
+}
+
+// Area-coverage settings for getting past the per-request result cap
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Coverage {
+    pub tiled: bool,
+    pub min_cell_radius_meters: f64,
+    pub max_depth: u32,
+    pub cell_overlap: f64,
+    // Size of the circles laid over a polygon area before searching each one
+    pub area_cell_radius_meters: f64,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage {
+            tiled: false,
+            min_cell_radius_meters: 250.0,
+            max_depth: 5,
+            cell_overlap: 1.1,
+            area_cell_radius_meters: 3000.0,
+        }
+    }
+}
+
+// Which venue sources to query and how to merge their results
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Providers {
+    pub google: bool,
+    pub google_places: GooglePlacesSettings,
+    pub overpass: OverpassSettings,
+    // Places from different providers closer than this with matching names are merged
+    pub merge_distance_meters: f64,
+}
+
+impl Default for Providers {
+    fn default() -> Self {
+        Providers {
+            google: true,
+            google_places: GooglePlacesSettings::default(),
+            overpass: OverpassSettings::default(),
+            merge_distance_meters: 75.0,
+        }
+    }
+}
+
+// Where Places and OAuth requests go; only changed to point at a proxy or a test stub
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GooglePlacesSettings {
+    pub base_url: String,
+    pub token_url: String,
+}
+
+impl Default for GooglePlacesSettings {
+    fn default() -> Self {
+        GooglePlacesSettings {
+            base_url: "https://places.googleapis.com/v1/".to_string(),
+            token_url: "https://oauth2.googleapis.com/token".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct OverpassSettings {
+    pub enabled: bool,
+    pub base_url: String,
+    pub timeout_secs: u64,
+}
+
+impl Default for OverpassSettings {
+    fn default() -> Self {
+        OverpassSettings {
+            enabled: false,
+            base_url: "https://overpass-api.de/api/interpreter".to_string(),
+            timeout_secs: 25,
+        }
+    }
 }
 
 #[derive(Parser)]
@@ -87,6 +468,10 @@ struct Cli {
     
     #[arg(long)]
     web: bool,
+
+    // Only discover venues and print what a real run would process and cost
+    #[arg(long)]
+    dry_run: bool,
 }
 
 fn cleanup_empty_directories(output_dir: &str) -> Result<(), Box<dyn std::error::Error>> {
@@ -95,40 +480,65 @@ fn cleanup_empty_directories(output_dir: &str) -> Result<(), Box<dyn std::error:
         return Ok(());
     }
 
-    for entry in std::fs::read_dir(path)? {
-        if let Ok(entry) = entry {
-            if entry.path().is_dir() {
-                let dir_path = entry.path();
-                let jpg_count = std::fs::read_dir(&dir_path)?
-                    .filter_map(Result::ok)
-                    .filter(|e| {
-                        e.path()
-                            .extension()
-                            .and_then(|ext| ext.to_str())
-                            .map(|ext| ext.to_lowercase() == "jpg")
-                            .unwrap_or(false)
-                    })
-                    .count();
+    for entry in std::fs::read_dir(path)?.flatten() {
+        if entry.path().is_dir() {
+            let dir_path = entry.path();
+            let photo_count = std::fs::read_dir(&dir_path)?
+                .filter_map(Result::ok)
+                .filter(|e| is_photo_file(&e.path()))
+                .count();
 
-                if jpg_count == 0 {
-                    println!("Removing empty directory: {}", dir_path.display());
-                    std::fs::remove_dir_all(&dir_path)?;
-                }
+            // Venue folders with a manifest stay, it keeps the scores of removed photos
+            if photo_count == 0 && !dir_path.join(MANIFEST_FILE).exists() {
+                println!("Removing empty directory: {}", dir_path.display());
+                std::fs::remove_dir_all(&dir_path)?;
             }
         }
     }
     Ok(())
 }
 
+// A database entry for a discovered place, carrying over whatever metadata was requested.
+// `pool_table_probability` is only meaningful after `rescore_venue`.
+fn venue_from_place(place: Place, image_score: Option<f32>) -> Venue {
+    let mut venue = Venue::new(
+        place.display_name.text,
+        place.id,
+        place.formatted_address,
+        0.0,
+        place.location.latitude,
+        place.location.longitude
+    );
+    venue.image_score = image_score;
+    venue.found_by = place.found_by;
+    venue.types = place.types;
+    venue.business_status = place.business_status;
+    venue.website = place.website_uri;
+    venue.phone = place.national_phone_number;
+    venue.rating = place.rating;
+    venue.price_level = place.price_level;
+    venue.opening_hours = place.regular_opening_hours;
+    venue
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn search_pool_tables(
-    config: Config,
+    mut config: Config,
     api_key: &str,
     cred_path: &str,
     output_dir: &str,
+    storage: Arc<dyn PhotoStorage>,
     model_path: &str,
+    costs: Arc<CostTracker>,
     status_callback: Option<impl Fn(&str) -> BoxFuture<'static, ()> + Send + Sync + 'static>,
 ) -> Result<Vec<Venue>, Box<dyn std::error::Error>> {
-    let mut all_places = PlacesResponse { places: Vec::new() };
+    if let Some(geocoded) = config.resolve_location(api_key, &costs).await? {
+        if let Some(callback) = &status_callback {
+            callback(&format!("Resolved \"{}\" to {} ({}, {}) via {}",
+                geocoded.query, geocoded.matched_name, geocoded.latitude, geocoded.longitude, geocoded.source)).await;
+        }
+    }
+    let search_area = config.search_area()?;
     
     // Load an existing collection of venues, or make a new one.
     let mut collection = match VenueCollection::load_from_json(Path::new("venues_database.json")) {
@@ -147,28 +557,29 @@ pub async fn search_pool_tables(
         }
     };
 
+    let classifier = classifier_for(&config.classifier, model_path, &config.python_worker)?;
+    if let Some(callback) = &status_callback {
+        callback(&format!("Classifying photos with the {}", classifier.name())).await;
+    }
+
     // Create the photos client
-    let photos_client = GooglePlacesClient::new(
+    let photos_client = Arc::new(GooglePlacesClient::new(
         cred_path,
         api_key,
-        output_dir,
-    );
+        storage.clone(),
+        config.retry.clone(),
+        costs.clone(),
+        config.downloads.clone(),
+        &config.providers.google_places,
+    ));
 
-    for place_type in &config.place_types {
-        match search_places(
-            api_key,
-            config.location.latitude,
-            config.location.longitude,
-            config.location.radius_meters,
-            place_type
-        ).await {
-            Ok(places) => all_places.places.extend(places.places),
-            Err(e) => eprintln!("Error searching for {}: {}", place_type, e)
-        }
-    }
+    let Discovery { places: all_places, mut budget_reached, .. } =
+        discover_places(&config, &search_area, api_key, &costs, &status_callback).await?;
 
-    // Process each place
-    let mut venues_processed = 0;
+    // Process each place. Photo downloads run ahead in background tasks, a few venues
+    // at a time, while the classifier works through the finished venues in order.
+    let venue_slots = Arc::new(Semaphore::new(config.downloads.concurrent_venues.max(1)));
+    let mut downloads = VecDeque::new();
     for place in all_places.places {
         if let Some(callback) = &status_callback {
             callback(&format!("Processing {}", place.display_name.text)).await;
@@ -190,53 +601,124 @@ pub async fn search_pool_tables(
             continue;
         }
 
-        match photos_client.get_place_photos(&place.id).await {
-            Ok(_) => {
-                let folder_path = Path::new(output_dir).join(&place.display_name.text);
-                // if let Some(callback) = &status_callback {
-                //     callback(&format!("Downloaded photos to {}", folder_path.display())).await;
-                // }
+        // Venues only known to OpenStreetMap have no Google photos to classify.
+        // A sport=billiards tag carries them on its type prior alone.
+        if place.id.starts_with(OSM_ID_PREFIX) {
+            if place.found_by.iter().any(|q| q == "osm:sport=billiards") {
+                let mut venue = venue_from_place(place, None);
+                rescore_venue(&mut venue, &config.fusion);
+                collection.add_venue(venue);
+            } else if let Some(callback) = &status_callback {
+                callback(&format!("No photos available for {}, skipping", place.display_name.text)).await;
+            }
+            continue;
+        }
+
+        if config.processing.skip_permanently_closed && place.is_permanently_closed() {
+            if let Some(callback) = &status_callback {
+                callback(&format!("{} is permanently closed, skipping", place.display_name.text)).await;
+            }
+            continue;
+        }
+
+        let client = photos_client.clone();
+        let costs = costs.clone();
+        let venue_slots = venue_slots.clone();
+        let place_id = place.id.clone();
+        let streaming = config.early_stop.enabled;
+        let download = tokio::spawn(async move {
+            let _slot = venue_slots.acquire_owned().await.ok()?;
+            // Stop between venues so every venue in the database was processed completely
+            if costs.budget_exhausted() {
+                return None;
+            }
+            let fetch = async {
+                let details = client.get_place_details(&place_id).await?;
+                let text = score_texts(details.texts());
+                // With early stopping photos are fetched one at a time as they are classified
+                let photos = if streaming {
+                    None
+                } else {
+                    Some(client.get_place_photos(&place_id, &details).await?)
+                };
+                Ok::<_, Box<dyn std::error::Error>>((text, details, photos))
+            };
+            Some(fetch.await.map_err(|e| (is_fatal_error(e.as_ref()), e.to_string())))
+        });
+        downloads.push_back((place, download));
+    }
+
+    let mut venues_processed = 0;
+    while let Some((place, download)) = downloads.pop_front() {
+        let result = match download.await {
+            Ok(Some(result)) => result,
+            Ok(None) => {
+                budget_reached = true;
+                continue;
+            },
+            Err(e) => {
+                eprintln!("Photo download for {} did not finish: {}", place.display_name.text, e);
+                continue;
+            }
+        };
+
+        let scan = match result {
+            Ok((text, details, photos)) => {
+                let scan = match photos {
+                    Some(photos) => scan_downloaded_photos(photos, &place.display_name.text, storage.as_ref(), &place.id, &classifier, &config, &status_callback).await,
+                    None => stream_venue_photos(&photos_client, &place.id, &details, storage.as_ref(), &classifier, &config, &status_callback).await,
+                };
+                scan.map(|scan| (text, scan)).map_err(|e| (is_fatal_error(e.as_ref()), e.to_string()))
+            },
+            Err(e) => Err(e),
+        };
+
+        match scan {
+            Ok((text, scan)) => {
+                let mut venue = venue_from_place(place, Some(scan.image_score));
+                venue.text_score = text.score;
+                venue.text_snippets = text.snippets;
+                venue.photos_skipped = scan.photos_skipped;
+                rescore_venue(&mut venue, &config.fusion);
+
+                let status = format!("Probability of pool table at {}: {:.2}% (images {:.2}%, reviews {:.2}%, type prior {:.2}%)", 
+                    venue.name,
+                    venue.pool_table_probability * 100.0,
+                    scan.image_score * 100.0,
+                    venue.text_score * 100.0,
+                    venue.type_prior * 100.0);
+                println!("Status update: {}", status);
+                if let Some(callback) = &status_callback {
+                    callback(&status).await;
+                }
                 
-                match run_python_script(
-                    &folder_path,
-                    model_path,
-                    &folder_path,
-                    config.processing.save_negative_images,
-                ) {
-                    Ok(probability) => {
-                        let status = format!("Probability of pool table at {}: {:.2}%", 
-                            place.display_name.text, probability * 100.0);
-                        println!("Status update: {}", status);
-                        if let Some(callback) = &status_callback {
-                            callback(&status).await;
-                        }
-                        
-                        let venue_name = place.display_name.text.clone();
-                        let venue = Venue::new(
-                            venue_name.clone(),
-                            place.id,
-                            place.formatted_address,
-                            probability,
-                            place.location.latitude,
-                            place.location.longitude
-                        );
-                        collection.add_venue(venue);
-                        
-                        // Increment processed count and save periodically
-                        venues_processed += 1;
-                        if venues_processed % 5 == 0 {
-                            if let Some(callback) = &status_callback {
-                                callback("Saving database checkpoint...").await;
-                            }
-                            if let Err(e) = collection.save_to_json(Path::new("venues_database.json")) {
-                                eprintln!("Error saving venue database checkpoint: {}", e);
-                            }
-                        }
-                    },
-                    Err(e) => eprintln!("Error: {}", e)
+                collection.add_venue(venue);
+                
+                // Increment processed count and save periodically
+                venues_processed += 1;
+                if venues_processed % 5 == 0 {
+                    if let Some(callback) = &status_callback {
+                        callback("Saving database checkpoint...").await;
+                    }
+                    if let Err(e) = collection.save_to_json(Path::new("venues_database.json")) {
+                        eprintln!("Error saving venue database checkpoint: {}", e);
+                    }
                 }
             },
-            Err(e) => eprintln!("Error getting photos for {}: {}", &place.display_name.text, e)
+            // Bad credentials or a spent quota fail every venue after this one too;
+            // keep what was processed so far and stop
+            Err((true, message)) => {
+                for (_, download) in downloads {
+                    download.abort();
+                }
+                collection.save_to_json(Path::new("venues_database.json"))?;
+                if let Some(callback) = &status_callback {
+                    callback(&format!("Stopping: {}", message)).await;
+                    callback(&costs.summary()).await;
+                }
+                return Err(message.into());
+            },
+            Err((false, message)) => eprintln!("Error getting photos for {}: {}", &place.display_name.text, message)
         }
     }
 
@@ -247,8 +729,26 @@ pub async fn search_pool_tables(
 
     // Final save to ensure we don't miss any venues
     collection.save_to_json(Path::new("venues_database.json"))?;
+
+    if let Some(callback) = &status_callback {
+        if budget_reached {
+            callback(&format!("Budget of ${:.2} reached, stopped early", config.budget.unwrap_or(0.0))).await;
+        }
+        callback(&costs.summary()).await;
+    }
     
-    Ok(collection.venues)
+    // Along a route, hand venues back in the order you would drive past them
+    let mut venues = collection.venues;
+    if let SearchArea::Route(route) = &search_area {
+        let mut keyed: Vec<(f64, Venue)> = venues
+            .into_iter()
+            .map(|v| (route.distance_along(v.latitude, v.longitude), v))
+            .collect();
+        keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+        venues = keyed.into_iter().map(|(_, v)| v).collect();
+    }
+
+    Ok(venues)
 }
 
 #[tokio::main]
@@ -261,26 +761,66 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let output_dir = env::var("OUTPUT_DIRECTORY").expect("OUTPUT_DIRECTORY must be set");
     let model_path = env::var("YOLO_WEIGHTS_PATH").expect("YOLO_WEIGHTS_PATH must be set");
 
+    let storage = storage_from_env(&output_dir)?;
+    // Photo folders used to be named after the venue; move them to the place_id layout.
+    // With S3 the output directory is only a download cache and never had that layout.
+    if storage_backend() == "local" {
+        match migrate_output_dir(&output_dir) {
+            Ok(0) => {},
+            Ok(migrated) => println!("Moved photos of {} venues to place id folders", migrated),
+            Err(e) => eprintln!("Error migrating photo folders in {}: {}", output_dir, e),
+        }
+    }
+    println!("Storing photos in {}", storage.describe());
+
     if cli.web {
         println!("Starting web server on http://localhost:3000");
+        let settings = SharedSettings::from_file(&cli.config)?;
         start_server(AppState {
             api_key,
             cred_path,
             output_dir,
             model_path,
+            storage,
+            settings,
         }).await?;
     } else {
-        let config: Config = {
+        let mut config: Config = {
             let file = File::open(&cli.config)?;
             serde_yaml::from_reader(file)?
         };
+        // Every billable call in this run is counted here
+        let costs = Arc::new(CostTracker::new(config.prices.clone(), config.budget));
+        if cli.dry_run {
+            let plan = plan_search(
+                config,
+                &api_key,
+                &costs,
+                Some(|msg: &str| -> BoxFuture<'static, ()> {
+                    let msg = msg.to_string();
+                    Box::pin(async move {
+                        println!("{}", msg);
+                    })
+                })
+            ).await?;
+            println!("{}", plan);
+            return Ok(());
+        }
+
+        if let Some(geocoded) = config.resolve_location(&api_key, &costs).await? {
+            println!("Resolved \"{}\" to {} ({}, {}) via {}",
+                geocoded.query, geocoded.matched_name, geocoded.latitude, geocoded.longitude, geocoded.source);
+        }
+        let search_area = config.search_area()?;
 
         let venues = search_pool_tables(
             config,
             &api_key,
             &cred_path,
             &output_dir,
+            storage,
             &model_path,
+            costs,
             Some(|msg: &str| -> BoxFuture<'static, ()> {
                 let msg = msg.to_string(); // Clone the message before moving
                 Box::pin(async move {
@@ -289,6 +829,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             })
         ).await?;
 
+        // A route search only reports venues inside the corridor, in driving order
+        let venues: Vec<Venue> = match &search_area {
+            SearchArea::Route(_) => venues
+                .into_iter()
+                .filter(|v| search_area.contains(v.latitude, v.longitude))
+                .collect(),
+            _ => venues,
+        };
+
         // Save filtered results to CSV
         let config_name = Path::new(&cli.config)
             .file_stem()
@@ -2,18 +2,58 @@ use chrono::{DateTime, Utc, Duration};
 use serde::{Serialize, Deserialize};
 use std::path::Path;
 use std::io::Write;
+use crate::google_places_search::OpeningHours;
+use crate::score_fusion::FusionMethod;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Venue {    
     pub name: String,
     pub place_id: String,
     pub address: String,
+    // The fused score; the evidence behind it is kept in the fields below
     pub pool_table_probability: f32,
     pub processed_date: DateTime<Utc>,
     #[serde(default)] 
     pub human_approved: i32,
+    #[serde(default)]
+    pub human_rejected: i32,
     pub latitude: f64,
     pub longitude: f64,
+    // Search queries that surfaced this venue, e.g. "type:bar", "text:billiards"
+    #[serde(default)]
+    pub found_by: Vec<String>,
+    // Place metadata from Google (website and phone also from OpenStreetMap)
+    #[serde(default)]
+    pub types: Vec<String>,
+    #[serde(default)]
+    pub business_status: Option<String>,
+    #[serde(default)]
+    pub website: Option<String>,
+    #[serde(default)]
+    pub phone: Option<String>,
+    #[serde(default)]
+    pub rating: Option<f64>,
+    #[serde(default)]
+    pub price_level: Option<String>,
+    #[serde(default)]
+    pub opening_hours: Option<OpeningHours>,
+    // Pool-table evidence found in reviews and the editorial summary
+    #[serde(default)]
+    pub text_score: f32,
+    #[serde(default)]
+    pub text_snippets: Vec<String>,
+    // Highest image confidence from the classifier, None when no photos were classified
+    #[serde(default)]
+    pub image_score: Option<f32>,
+    // Prior from the place types and the queries that found the venue
+    #[serde(default)]
+    pub type_prior: f32,
+    // Which fusion produced pool_table_probability, None for venues scored before fusion
+    #[serde(default)]
+    pub score_method: Option<FusionMethod>,
+    // Photos Google listed that early stopping never downloaded
+    #[serde(default)]
+    pub photos_skipped: usize,
 }
 
 impl Venue {
@@ -25,8 +65,23 @@ impl Venue {
             pool_table_probability: probability,
             processed_date: Utc::now(),
             human_approved: 0,
+            human_rejected: 0,
             latitude: lat,
             longitude: lon,
+            found_by: Vec::new(),
+            types: Vec::new(),
+            business_status: None,
+            website: None,
+            phone: None,
+            rating: None,
+            price_level: None,
+            opening_hours: None,
+            text_score: 0.0,
+            text_snippets: Vec::new(),
+            image_score: None,
+            type_prior: 0.0,
+            score_method: None,
+            photos_skipped: 0,
         }
     }
 }
@@ -61,8 +116,14 @@ impl VenueCollection {
         let json_str = std::fs::read_to_string(file_path)?;
         println!("Read {} bytes from database file", json_str.len());
         match serde_json::from_str::<VenueCollection>(&json_str) {
-            Ok(collection) => {
+            Ok(mut collection) => {
                 println!("Successfully parsed database with {} venues", collection.venues.len());
+                // Venues scored before fusion only kept the image score, in pool_table_probability
+                for venue in &mut collection.venues {
+                    if venue.score_method.is_none() && venue.image_score.is_none() {
+                        venue.image_score = Some(venue.pool_table_probability);
+                    }
+                }
                 Ok(collection)
             },
             Err(e) => {
@@ -95,17 +156,28 @@ impl VenueCollection {
         let mut writer = std::fs::File::create(file_path)?;
         
         // Write CSV header
-        writeln!(writer, "Name,Address,Pool Table Probability,Place ID")?;
+        writeln!(writer, "Name,Address,Pool Table Probability,Place ID,Types,Business Status,Website,Phone,Rating,Price Level,Opening Hours,Text Score")?;
         
         // Write each venue
         for venue in filtered_venues {
             writeln!(
                 writer,
-                "{},{},{:.2}%,{}",
+                "{},{},{:.2}%,{},{},{},{},{},{},{},{},{:.2}%",
                 venue.name.replace(",", ""),  // Remove commas from names to avoid CSV issues
                 venue.address.replace(",", ""),  // Remove commas from addresses
                 venue.pool_table_probability * 100.0,
-                venue.place_id
+                venue.place_id,
+                venue.types.join(" "),
+                venue.business_status.as_deref().unwrap_or(""),
+                venue.website.as_deref().unwrap_or("").replace(",", "%2C"),
+                venue.phone.as_deref().unwrap_or(""),
+                venue.rating.map(|r| format!("{:.1}", r)).unwrap_or_default(),
+                venue.price_level.as_deref().unwrap_or(""),
+                venue.opening_hours
+                    .as_ref()
+                    .map(|h| h.weekday_descriptions.join("; ").replace(",", ""))
+                    .unwrap_or_default(),
+                venue.text_score * 100.0
             )?;
         }
         
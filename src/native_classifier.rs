@@ -0,0 +1,320 @@
+use image::imageops::FilterType;
+use lazy_static::lazy_static;
+use ndarray::{Array4, ArrayD};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tract_onnx::pb::ModelProto;
+use tract_onnx::prelude::*;
+
+// Classifies photos in-process with an ONNX export of the YOLOv8 classification model
+// (`yolo export model=yolo_weights.pt format=onnx`), instead of starting Python.
+// The model runs on tract, a pure-Rust ONNX runtime.
+
+// Class names of the fine-tuned model, for exports without ultralytics metadata
+const DEFAULT_CLASS_NAMES: [&str; 2] = ["no_pool_table", "pool_table"];
+const DEFAULT_IMAGE_SIZE: u32 = 224;
+
+lazy_static! {
+    // Models are loaded once per process, keyed by path
+    static ref LOADED_MODELS: Mutex<HashMap<String, Arc<NativeClassifier>>> = Mutex::new(HashMap::new());
+}
+
+// The classifier's top class for one photo, as PoolTableInference.py reports it
+#[derive(Debug, Clone)]
+pub struct Prediction {
+    pub class_name: String,
+    pub confidence: f32,
+    pub class_index: usize,
+}
+
+type TractModel = SimplePlan<TypedFact, Box<dyn TypedOp>, Graph<TypedFact, Box<dyn TypedOp>>>;
+
+pub struct NativeClassifier {
+    model: TractModel,
+    // Whether the graph ends in Softmax, so its output is already probabilities
+    softmax_output: bool,
+    class_names: Vec<String>,
+    image_size: u32,
+}
+
+pub fn is_onnx_model(model_path: &str) -> bool {
+    Path::new(model_path)
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("onnx"))
+        .unwrap_or(false)
+}
+
+// The classifier for `model_path`, loading it on first use
+pub fn shared_classifier(model_path: &str) -> Result<Arc<NativeClassifier>, Box<dyn std::error::Error>> {
+    let mut models = LOADED_MODELS.lock().map_err(|_| "Classifier cache poisoned")?;
+    if let Some(classifier) = models.get(model_path) {
+        return Ok(classifier.clone());
+    }
+    let classifier = Arc::new(NativeClassifier::load(Path::new(model_path))?);
+    println!("Loaded ONNX classifier {} ({} classes, {}px)",
+        model_path, classifier.class_names.len(), classifier.image_size);
+    models.insert(model_path.to_string(), classifier.clone());
+    Ok(classifier)
+}
+
+// ultralytics writes names as a Python dict, e.g. {0: 'no_pool_table', 1: 'pool_table'}
+fn parse_class_names(names: &str) -> Option<Vec<String>> {
+    let mut entries: Vec<(usize, String)> = names
+        .trim()
+        .trim_start_matches('{')
+        .trim_end_matches('}')
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (index, name) = entry.split_once(':')?;
+            let name = name.trim().trim_matches(|c| c == '\'' || c == '"');
+            Some((index.trim().parse().ok()?, name.to_string()))
+        })
+        .collect::<Option<_>>()?;
+    entries.sort();
+    Some(entries.into_iter().map(|(_, name)| name).collect())
+}
+
+// imgsz is written as [224, 224]
+fn parse_image_size(imgsz: &str) -> Option<u32> {
+    imgsz.trim_matches(|c| c == '[' || c == ']').split(',').next()?.trim().parse().ok()
+}
+
+impl NativeClassifier {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let proto = tract_onnx::onnx()
+            .proto_model_for_path(path)
+            .map_err(|e| format!("Could not read ONNX model {}: {}", path.display(), e))?;
+        Self::from_proto(&proto)
+    }
+
+    fn from_proto(proto: &ModelProto) -> Result<Self, Box<dyn std::error::Error>> {
+        // ultralytics writes class names and image size to the model's metadata_props
+        let metadata: HashMap<&str, &str> = proto.metadata_props
+            .iter()
+            .map(|entry| (entry.key.as_str(), entry.value.as_str()))
+            .collect();
+        let class_names = metadata.get("names")
+            .and_then(|names| parse_class_names(names))
+            .unwrap_or_else(|| DEFAULT_CLASS_NAMES.iter().map(|name| name.to_string()).collect());
+        let image_size = metadata.get("imgsz")
+            .and_then(|imgsz| parse_image_size(imgsz))
+            .unwrap_or(DEFAULT_IMAGE_SIZE);
+
+        let graph = proto.graph.as_ref().ok_or("ONNX model has no graph")?;
+        let output_name = graph.output.first().map(|output| output.name.as_str()).ok_or("ONNX model has no output")?;
+        let softmax_output = graph.node
+            .iter()
+            .any(|node| node.op_type == "Softmax" && node.output.iter().any(|name| name == output_name));
+
+        let size = image_size as usize;
+        let model = tract_onnx::onnx()
+            .model_for_proto_model(proto)?
+            .with_input_fact(0, f32::fact([1, 3, size, size]).into())?
+            .into_optimized()?
+            .into_runnable()?;
+        Ok(NativeClassifier { model, softmax_output, class_names, image_size })
+    }
+
+    // The ultralytics classification transform: center square crop, resize to the
+    // model's size, RGB scaled to 0..1 in NCHW order
+    fn preprocess(&self, path: &Path) -> Result<ArrayD<f32>, Box<dyn std::error::Error>> {
+        let image = image::open(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let side = image.width().min(image.height());
+        let cropped = image.crop_imm((image.width() - side) / 2, (image.height() - side) / 2, side, side);
+        let size = self.image_size;
+        let rgb = cropped.resize_exact(size, size, FilterType::Triangle).to_rgb8();
+
+        let mut tensor = Array4::<f32>::zeros((1, 3, size as usize, size as usize));
+        for (x, y, pixel) in rgb.enumerate_pixels() {
+            for channel in 0..3 {
+                tensor[[0, channel, y as usize, x as usize]] = pixel[channel] as f32 / 255.0;
+            }
+        }
+        Ok(tensor.into_dyn())
+    }
+
+    pub fn classify(&self, path: &Path) -> Result<Prediction, Box<dyn std::error::Error>> {
+        let outputs = self.model.run(tvec!(Tensor::from(self.preprocess(path)?).into()))?;
+        let output = outputs.first().ok_or("Classifier returned no output")?;
+        let mut scores: Vec<f32> = output.to_array_view::<f32>()?.iter().copied().collect();
+        // Exports normally end in Softmax; turn raw logits into probabilities otherwise
+        if !self.softmax_output {
+            let max = scores.iter().fold(f32::NEG_INFINITY, |m, v| m.max(*v));
+            scores.iter_mut().for_each(|v| *v = (*v - max).exp());
+            let sum: f32 = scores.iter().sum();
+            scores.iter_mut().for_each(|v| *v /= sum);
+        }
+
+        let (class_index, confidence) = scores
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .ok_or("Classifier returned no scores")?;
+        let class_name = self.class_names
+            .get(class_index)
+            .cloned()
+            .unwrap_or_else(|| class_index.to_string());
+        Ok(Prediction { class_name, confidence, class_index })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tract_onnx::pb::{
+        tensor_proto::DataType, type_proto, GraphProto, NodeProto, OperatorSetIdProto, StringStringEntryProto, TensorProto,
+        TypeProto, ValueInfoProto,
+    };
+
+    fn node(op_type: &str, inputs: &[&str], output: &str) -> NodeProto {
+        NodeProto {
+            op_type: op_type.to_string(),
+            input: inputs.iter().map(|input| input.to_string()).collect(),
+            output: vec![output.to_string()],
+            ..Default::default()
+        }
+    }
+
+    // A stand-in for a YOLOv8-cls export: average colour -> 2 logits -> Softmax.
+    // Mostly red photos score no_pool_table, mostly green ones pool_table.
+    fn colour_model(softmax: bool, metadata: &[(&str, &str)]) -> ModelProto {
+        let weights = TensorProto {
+            name: "weights".to_string(),
+            dims: vec![3, 2],
+            data_type: DataType::Float as i32,
+            float_data: vec![10.0, 0.0, 0.0, 10.0, 0.0, 0.0],
+            ..Default::default()
+        };
+        let mut nodes = vec![
+            node("GlobalAveragePool", &["images"], "pooled"),
+            node("Flatten", &["pooled"], "flat"),
+            node("MatMul", &["flat", "weights"], "logits"),
+        ];
+        if softmax {
+            nodes.push(node("Softmax", &["logits"], "output0"));
+        }
+        let output = if softmax { "output0" } else { "logits" };
+        ModelProto {
+            ir_version: 8,
+            opset_import: vec![OperatorSetIdProto { domain: String::new(), version: 13 }],
+            graph: Some(GraphProto {
+                node: nodes,
+                initializer: vec![weights],
+                input: vec![ValueInfoProto {
+                    name: "images".to_string(),
+                    r#type: Some(TypeProto {
+                        value: Some(type_proto::Value::TensorType(type_proto::Tensor { elem_type: DataType::Float as i32, shape: None })),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }],
+                output: vec![ValueInfoProto { name: output.to_string(), ..Default::default() }],
+                ..Default::default()
+            }),
+            metadata_props: metadata
+                .iter()
+                .map(|(key, value)| StringStringEntryProto { key: key.to_string(), value: value.to_string() })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn solid_photo(folder: &Path, name: &str, colour: [u8; 3]) -> std::path::PathBuf {
+        let path = folder.join(name);
+        image::RgbImage::from_pixel(300, 200, image::Rgb(colour)).save(&path).unwrap();
+        path
+    }
+
+    fn scratch_folder(name: &str) -> std::path::PathBuf {
+        let folder = std::env::temp_dir().join(format!("racknroll_native_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    #[test]
+    fn classifies_with_the_exports_metadata() {
+        let model = colour_model(true, &[("names", "{0: 'no_pool_table', 1: 'pool_table'}"), ("imgsz", "[32, 32]")]);
+        let classifier = NativeClassifier::from_proto(&model).unwrap();
+        assert_eq!(classifier.image_size, 32);
+        assert!(classifier.softmax_output);
+
+        let folder = scratch_folder("metadata");
+        let green = classifier.classify(&solid_photo(&folder, "green.jpg", [0, 200, 0])).unwrap();
+        let red = classifier.classify(&solid_photo(&folder, "red.jpg", [200, 0, 0])).unwrap();
+        std::fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(green.class_name, "pool_table");
+        assert_eq!(green.class_index, 1);
+        assert!(green.confidence > 0.99, "{:?}", green);
+        assert_eq!(red.class_name, "no_pool_table");
+        assert!(red.confidence > 0.99, "{:?}", red);
+    }
+
+    #[test]
+    fn applies_softmax_to_raw_logits_and_defaults_without_metadata() {
+        let classifier = NativeClassifier::from_proto(&colour_model(false, &[])).unwrap();
+        assert_eq!(classifier.image_size, DEFAULT_IMAGE_SIZE);
+        assert!(!classifier.softmax_output);
+
+        let folder = scratch_folder("logits");
+        // Equal red and green: logits tie, so each class gets one half
+        let prediction = classifier.classify(&solid_photo(&folder, "olive.jpg", [128, 128, 0])).unwrap();
+        std::fs::remove_dir_all(&folder).unwrap();
+        assert!((prediction.confidence - 0.5).abs() < 0.01, "{:?}", prediction);
+        assert!(DEFAULT_CLASS_NAMES.contains(&prediction.class_name.as_str()));
+    }
+
+    #[test]
+    fn parses_ultralytics_metadata() {
+        assert_eq!(parse_class_names("{0: 'no_pool_table', 1: 'pool_table'}").unwrap(), vec!["no_pool_table", "pool_table"]);
+        assert_eq!(parse_class_names("{1: \"b\", 0: \"a\"}").unwrap(), vec!["a", "b"]);
+        assert_eq!(parse_image_size("[224, 224]"), Some(224));
+        assert_eq!(parse_image_size("size"), None);
+    }
+
+    #[test]
+    fn rejects_a_model_whose_shapes_do_not_fit() {
+        // Four weight rows for a three-channel input: an error at load, not a panic
+        let mut model = colour_model(true, &[("imgsz", "[32, 32]")]);
+        let weights = &mut model.graph.as_mut().unwrap().initializer[0];
+        weights.dims = vec![4, 2];
+        weights.float_data = vec![0.0; 8];
+        assert!(NativeClassifier::from_proto(&model).is_err());
+    }
+
+    #[test]
+    fn rejects_a_missing_model() {
+        assert!(NativeClassifier::load(Path::new("does_not_exist.onnx")).is_err());
+    }
+
+    // Compares a real YOLOv8n-cls export against ultralytics' own predictions.
+    // YOLO_ONNX_TEST_MODEL is the .onnx export, YOLO_ONNX_TEST_EXPECTED a JSON file
+    // of {"photo path": {"class_name": ..., "confidence": ...}} from
+    // ultralytics_predictions.py. Run with `-- --ignored`.
+    #[test]
+    #[ignore = "needs YOLO_ONNX_TEST_MODEL and YOLO_ONNX_TEST_EXPECTED"]
+    fn matches_ultralytics_on_a_real_export() {
+        let model_path = std::env::var("YOLO_ONNX_TEST_MODEL").expect("YOLO_ONNX_TEST_MODEL must be set");
+        let expected_path = std::env::var("YOLO_ONNX_TEST_EXPECTED").expect("YOLO_ONNX_TEST_EXPECTED must be set");
+        #[derive(serde::Deserialize)]
+        struct Expected {
+            class_name: String,
+            confidence: f32,
+        }
+        let expected: HashMap<String, Expected> =
+            serde_json::from_str(&std::fs::read_to_string(&expected_path).unwrap()).unwrap();
+        assert!(!expected.is_empty(), "No predictions in {}", expected_path);
+
+        let classifier = NativeClassifier::load(Path::new(&model_path)).unwrap();
+        for (photo, want) in &expected {
+            let got = classifier.classify(Path::new(photo)).unwrap();
+            assert_eq!(got.class_name, want.class_name, "{}", photo);
+            // Resampling differs slightly from torchvision's, so allow a little slack
+            assert!((got.confidence - want.confidence).abs() < 0.05,
+                "{}: {} here, {} from ultralytics", photo, got.confidence, want.confidence);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use futures::future::BoxFuture;
+use crate::{Config, OverpassSettings};
+use crate::cost::CostTracker;
+use crate::geometry::SearchCircle;
+use crate::google_places_search::{DisplayName, Location, Place, PlacesResponse};
+use crate::place_provider::PlaceProvider;
+
+// Place ids for OpenStreetMap venues look like "osm:node/123456"
+pub const OSM_ID_PREFIX: &str = "osm:";
+
+// Tags we query for, also used to label `found_by`
+const VENUE_TAGS: [(&str, &str); 4] = [
+    ("amenity", "bar"),
+    ("amenity", "pub"),
+    ("tourism", "hotel"),
+    ("sport", "billiards"),
+];
+
+#[derive(Debug, Deserialize)]
+struct OverpassResponse {
+    #[serde(default)]
+    elements: Vec<OverpassElement>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OverpassCenter {
+    lat: f64,
+    lon: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct OverpassElement {
+    #[serde(rename = "type")]
+    element_type: String,
+    id: u64,
+    // Nodes carry lat/lon directly, ways and relations get a center from `out center`
+    lat: Option<f64>,
+    lon: Option<f64>,
+    center: Option<OverpassCenter>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+impl OverpassElement {
+    fn into_place(self) -> Option<Place> {
+        let name = self.tags.get("name")?.clone();
+        let (latitude, longitude) = match (self.lat, self.lon, &self.center) {
+            (Some(lat), Some(lon), _) => (lat, lon),
+            (_, _, Some(center)) => (center.lat, center.lon),
+            _ => return None,
+        };
+
+        let address = ["addr:housenumber", "addr:street", "addr:city", "addr:postcode"]
+            .iter()
+            .filter_map(|key| self.tags.get(*key).map(|v| v.as_str()))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let found_by = VENUE_TAGS
+            .iter()
+            .filter(|(key, value)| self.tags.get(*key).map(|v| v == value).unwrap_or(false))
+            .map(|(key, value)| format!("osm:{}={}", key, value))
+            .collect();
+
+        Some(Place {
+            id: format!("{}{}/{}", OSM_ID_PREFIX, self.element_type, self.id),
+            display_name: DisplayName { text: name },
+            location: Location { latitude, longitude },
+            formatted_address: address,
+            types: Vec::new(),
+            business_status: None,
+            website_uri: self.tags.get("website").cloned(),
+            national_phone_number: self.tags.get("phone").cloned(),
+            rating: None,
+            price_level: None,
+            // OSM opening_hours uses its own syntax, not Google's periods
+            regular_opening_hours: None,
+            found_by,
+        })
+    }
+}
+
+// Build the Overpass QL query for every venue tag inside the circle
+fn build_query(area: &SearchCircle, timeout_secs: u64) -> String {
+    let around = format!("(around:{:.0},{},{})", area.radius_meters, area.latitude, area.longitude);
+    let clauses: String = VENUE_TAGS
+        .iter()
+        .map(|(key, value)| format!("  nwr[\"{}\"=\"{}\"]{};\n", key, value, around))
+        .collect();
+    format!("[out:json][timeout:{}];\n(\n{});\nout center tags;", timeout_secs, clauses)
+}
+
+// OpenStreetMap venues through the Overpass API
+pub struct OverpassProvider {
+    base_url: String,
+    timeout_secs: u64,
+}
+
+impl OverpassProvider {
+    pub fn new(settings: &OverpassSettings) -> Self {
+        OverpassProvider {
+            base_url: settings.base_url.clone(),
+            timeout_secs: settings.timeout_secs,
+        }
+    }
+
+    pub async fn search(&self, area: SearchCircle) -> Result<PlacesResponse, Box<dyn std::error::Error>> {
+        let query = build_query(&area, self.timeout_secs);
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(&self.base_url)
+            .form(&[("data", query)])
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(format!("Overpass returned {}", response.status()).into());
+        }
+
+        let result = response.text().await?;
+        let overpass: OverpassResponse = serde_json::from_str(&result)?;
+
+        // Unnamed elements (a pool table mapped inside a bar, for example) have nothing to show
+        let places = overpass.elements
+            .into_iter()
+            .filter_map(OverpassElement::into_place)
+            .collect();
+        Ok(PlacesResponse { places })
+    }
+}
+
+impl PlaceProvider for OverpassProvider {
+    fn name(&self) -> &str {
+        "OpenStreetMap Overpass"
+    }
+
+    fn find_places<'a>(
+        &'a self,
+        area: SearchCircle,
+        _config: &'a Config,
+        _costs: &'a CostTracker,
+    ) -> BoxFuture<'a, Result<PlacesResponse, Box<dyn std::error::Error>>> {
+        Box::pin(self.search(area))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+
+    // What a local Overpass instance answers for a small area
+    const OVERPASS_FIXTURE: &str = r#"{
+        "elements": [
+            {"type": "node", "id": 1, "lat": 40.7290, "lon": -73.9911,
+             "tags": {"name": "Joe's Pub", "amenity": "pub", "sport": "billiards",
+                      "addr:housenumber": "425", "addr:street": "Lafayette Street", "website": "https://joespub.com"}},
+            {"type": "way", "id": 2, "center": {"lat": 40.7301, "lon": -73.9920},
+             "tags": {"name": "Hotel Lafayette", "tourism": "hotel"}},
+            {"type": "node", "id": 3, "lat": 40.7295, "lon": -73.9915, "tags": {"sport": "billiards"}}
+        ]
+    }"#;
+
+    #[test]
+    fn query_covers_every_venue_tag_around_the_circle() {
+        let query = build_query(&SearchCircle::new(40.729, -73.991, 500.0), 25);
+        assert!(query.starts_with("[out:json][timeout:25];"));
+        assert_eq!(query.matches("(around:500,40.729,-73.991)").count(), VENUE_TAGS.len());
+        assert!(query.contains("nwr[\"sport\"=\"billiards\"]"));
+    }
+
+    #[actix_web::test]
+    async fn search_reads_venues_from_a_local_overpass() {
+        let server = HttpServer::new(|| App::new().route("/api/interpreter", web::post().to(|form: web::Form<HashMap<String, String>>| async move {
+            if !form.get("data").map(|q| q.contains("out center tags")).unwrap_or(false) {
+                return HttpResponse::BadRequest().finish();
+            }
+            HttpResponse::Ok().content_type("application/json").body(OVERPASS_FIXTURE)
+        })))
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        let provider = OverpassProvider::new(&OverpassSettings {
+            enabled: true,
+            base_url: format!("http://{}/api/interpreter", address),
+            timeout_secs: 5,
+        });
+        let places = provider.search(SearchCircle::new(40.729, -73.991, 500.0)).await.unwrap().places;
+
+        // The unnamed billiards node is dropped
+        assert_eq!(places.len(), 2);
+        assert_eq!(places[0].id, "osm:node/1");
+        assert_eq!(places[0].display_name.text, "Joe's Pub");
+        assert_eq!(places[0].formatted_address, "425 Lafayette Street");
+        assert_eq!(places[0].website_uri.as_deref(), Some("https://joespub.com"));
+        assert_eq!(places[0].found_by, vec!["osm:amenity=pub", "osm:sport=billiards"]);
+        assert_eq!(places[1].id, "osm:way/2");
+        assert_eq!(places[1].location.latitude, 40.7301);
+    }
+}
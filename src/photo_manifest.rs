@@ -0,0 +1,419 @@
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use crate::classifier::ImageResult;
+use crate::google_places_photos_reviews::{is_photo_file, AuthorAttribution, PhotoDetails};
+use crate::storage::PhotoStorage;
+use crate::tiled_classifier::TileRegion;
+
+// Photos live in output_dir/<place_id>/, next to a manifest.json describing them.
+// Folders used to be named after the venue, which broke on names with '/' and on
+// chains where many venues share one name.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+// Feedback folders kept directly under output_dir, never venue folders
+pub const CONFIRMED_DIR: &str = "confirmed_pool_tables";
+pub const NEGATIVE_DIR: &str = "no_pool_table_training";
+
+// A photo shows a table when the classifier's top class is pool_table, which for its
+// two classes means a pool-table score of at least one half
+const POSITIVE_SCORE: f32 = 0.5;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestPhoto {
+    // File name inside the venue folder
+    pub file: String,
+    // Google photo resource name, None for photos moved over from the name-keyed layout
+    #[serde(default)]
+    pub photo_name: Option<String>,
+    // Pool-table score from the classifier, None until the photo is classified
+    #[serde(default)]
+    pub score: Option<f32>,
+    // The classifier's top class and its confidence, which the score comes from
+    #[serde(default)]
+    pub class_name: Option<String>,
+    #[serde(default)]
+    pub confidence: Option<f32>,
+    #[serde(default)]
+    pub class_index: Option<usize>,
+    // The part of the photo the score came from, when tiled inference found it in a tile
+    #[serde(default)]
+    pub tile: Option<TileRegion>,
+    // Size and credit as Google listed them; unknown for migrated photos
+    #[serde(default)]
+    pub width_px: Option<u32>,
+    #[serde(default)]
+    pub height_px: Option<u32>,
+    #[serde(default)]
+    pub author_attributions: Vec<AuthorAttribution>,
+}
+
+impl ManifestPhoto {
+    fn new(file: String, photo_name: Option<String>) -> Self {
+        ManifestPhoto {
+            file,
+            photo_name,
+            score: None,
+            class_name: None,
+            confidence: None,
+            class_index: None,
+            tile: None,
+            width_px: None,
+            height_px: None,
+            author_attributions: Vec::new(),
+        }
+    }
+
+    fn set_result(&mut self, result: Option<&ImageResult>) {
+        self.score = result.map(|r| r.pool_table_score());
+        self.class_name = result.map(|r| r.class_name.clone());
+        self.confidence = result.map(|r| r.confidence);
+        self.class_index = result.and_then(|r| r.class_index);
+        self.tile = result.and_then(|r| r.tile);
+    }
+
+    fn set_details(&mut self, photo: &PhotoDetails) {
+        self.width_px = photo.width_px;
+        self.height_px = photo.height_px;
+        self.author_attributions = photo.author_attributions.clone();
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PhotoManifest {
+    pub place_id: String,
+    pub venue_name: String,
+    // Photos stay listed after the classifier or feedback removes their file,
+    // so their scores are not lost
+    pub photos: Vec<ManifestPhoto>,
+}
+
+// Folder name for a place. Google place ids are already safe; OSM ids carry ':' and '/'.
+pub fn venue_dir_name(place_id: &str) -> String {
+    place_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+// The venue's folder in the local output directory, where the classifier reads photos
+pub fn venue_dir(output_dir: &str, place_id: &str) -> PathBuf {
+    Path::new(output_dir).join(venue_dir_name(place_id))
+}
+
+// Storage key of a file in the venue's folder
+pub fn venue_key(place_id: &str, file: &str) -> String {
+    format!("{}/{}", venue_dir_name(place_id), file)
+}
+
+impl PhotoManifest {
+    pub fn new(place_id: &str, venue_name: &str) -> Self {
+        PhotoManifest {
+            place_id: place_id.to_string(),
+            venue_name: venue_name.to_string(),
+            photos: Vec::new(),
+        }
+    }
+
+    // None when the venue has no manifest yet. A storage failure is an error rather
+    // than None, so a flaky connection never replaces a manifest with an empty one.
+    pub async fn load(storage: &dyn PhotoStorage, place_id: &str) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        match storage.get(&venue_key(place_id, MANIFEST_FILE)).await? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn save(&self, storage: &dyn PhotoStorage) -> Result<(), Box<dyn std::error::Error>> {
+        storage.put(&venue_key(&self.place_id, MANIFEST_FILE), serde_json::to_vec_pretty(self)?).await
+    }
+
+    fn load_from_dir(dir: &Path) -> Option<Self> {
+        std::fs::read_to_string(dir.join(MANIFEST_FILE))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+    }
+
+    fn save_to_dir(&self, dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join(MANIFEST_FILE), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    // Record classifier results, keyed by file name
+    pub fn set_results(&mut self, results: &HashMap<String, ImageResult>) {
+        for photo in &mut self.photos {
+            if let Some(result) = results.get(&photo.file) {
+                photo.set_result(Some(result));
+            }
+        }
+    }
+
+    // A person said the photo shows no table. It stays listed so a rescan does not
+    // download it again, with a zero score so it no longer counts for the venue.
+    pub fn reject(&mut self, file: &str) -> bool {
+        match self.photos.iter_mut().find(|p| p.file == file) {
+            Some(photo) => {
+                photo.set_result(None);
+                photo.score = Some(0.0);
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn find(&self, photo_name: &str) -> Option<&ManifestPhoto> {
+        self.photos.iter().find(|p| p.photo_name.as_deref() == Some(photo_name))
+    }
+
+    // Point a Google photo at its newly saved file, adding it when it is new
+    pub fn record_download(&mut self, photo: &PhotoDetails, file: String) {
+        match self.photos.iter_mut().find(|p| p.photo_name.as_deref() == Some(photo.name.as_str())) {
+            Some(known) => {
+                known.file = file;
+                known.set_result(None);
+                known.set_details(photo);
+            },
+            None => {
+                let mut new_photo = ManifestPhoto::new(file, Some(photo.name.clone()));
+                new_photo.set_details(photo);
+                self.photos.push(new_photo);
+            },
+        }
+    }
+
+    // Refresh size and attributions of a photo already in the manifest
+    pub fn update_details(&mut self, photo: &PhotoDetails) {
+        if let Some(known) = self.photos.iter_mut().find(|p| p.photo_name.as_deref() == Some(photo.name.as_str())) {
+            known.set_details(photo);
+        }
+    }
+
+    // File index for the next download. Google reorders its photo list between scans,
+    // so new photos are numbered after every file the venue ever had.
+    pub fn next_file_index(&self) -> usize {
+        self.photos
+            .iter()
+            .filter_map(|p| split_photo_file(&p.file))
+            .map(|(_, index)| index + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // The venue's image score over every photo scored so far: the highest score of a
+    // photo showing a table, 0.0 when none does, None when nothing was scored yet
+    pub fn image_score(&self) -> Option<f32> {
+        let scores: Vec<f32> = self.photos.iter().filter_map(|p| p.score).collect();
+        if scores.is_empty() {
+            return None;
+        }
+        Some(scores.into_iter().filter(|s| *s >= POSITIVE_SCORE).fold(0.0, f32::max))
+    }
+
+    // Listed photos whose files are still stored, given the keys in the venue's folder
+    pub fn existing_photos(&self, stored_keys: &HashSet<String>) -> Vec<ManifestPhoto> {
+        self.photos
+            .iter()
+            .filter(|photo| stored_keys.contains(&venue_key(&self.place_id, &photo.file)))
+            .cloned()
+            .collect()
+    }
+}
+
+// Keys stored in a venue's folder
+pub async fn stored_venue_keys(storage: &dyn PhotoStorage, place_id: &str) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+    Ok(storage.list(&venue_key(place_id, "")).await?.into_iter().collect())
+}
+
+// Photos of a venue still in storage, from its manifest or, without one, from the folder itself
+pub async fn venue_photos(storage: &dyn PhotoStorage, place_id: &str) -> Result<Vec<ManifestPhoto>, Box<dyn std::error::Error>> {
+    let stored = stored_venue_keys(storage, place_id).await?;
+    if let Some(manifest) = PhotoManifest::load(storage, place_id).await? {
+        return Ok(manifest.existing_photos(&stored));
+    }
+    let mut files: Vec<String> = stored
+        .iter()
+        .filter(|key| is_photo_file(Path::new(key)))
+        .filter_map(|key| Some(key.rsplit_once('/')?.1.to_string()))
+        .collect();
+    files.sort();
+    Ok(files.into_iter().map(|file| ManifestPhoto::new(file, None)).collect())
+}
+
+// Photos are saved as {place_id}_{index}.{ext}; Google place ids never contain '.'
+fn split_photo_file(file: &str) -> Option<(&str, usize)> {
+    let stem = file.split('.').next()?;
+    let (place_id, index) = stem.rsplit_once('_')?;
+    if place_id.is_empty() {
+        return None;
+    }
+    Some((place_id, index.parse().ok()?))
+}
+
+// Move photos from the old name-keyed folders into place_id folders with a manifest.
+// Folders that already have a manifest are left alone, so running it again does nothing.
+// Only the local output directory ever had the old layout.
+pub fn migrate_output_dir(output_dir: &str) -> Result<usize, Box<dyn std::error::Error>> {
+    let root = Path::new(output_dir);
+    if !root.exists() {
+        return Ok(0);
+    }
+
+    let mut migrated = 0;
+    for entry in std::fs::read_dir(root)?.flatten() {
+        let old_dir = entry.path();
+        let dir_name = entry.file_name().to_string_lossy().to_string();
+        if !old_dir.is_dir()
+            || dir_name == CONFIRMED_DIR
+            || dir_name == NEGATIVE_DIR
+            || old_dir.join(MANIFEST_FILE).exists()
+        {
+            continue;
+        }
+
+        // Same-named venues shared one folder, so split its photos by place id
+        let mut by_place: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for photo in std::fs::read_dir(&old_dir)?.flatten() {
+            let file = photo.file_name().to_string_lossy().to_string();
+            if !is_photo_file(&photo.path()) {
+                continue;
+            }
+            match split_photo_file(&file) {
+                Some((place_id, _)) => by_place.entry(place_id.to_string()).or_default().push(file),
+                None => eprintln!("Not migrating {}: no place id in the file name", photo.path().display()),
+            }
+        }
+
+        for (place_id, mut files) in by_place {
+            let new_dir = venue_dir(output_dir, &place_id);
+            let mut manifest = PhotoManifest::load_from_dir(&new_dir)
+                .unwrap_or_else(|| PhotoManifest::new(&place_id, &dir_name));
+            files.sort();
+            for file in files {
+                if new_dir != old_dir {
+                    std::fs::create_dir_all(&new_dir)?;
+                    std::fs::rename(old_dir.join(&file), new_dir.join(&file))?;
+                }
+                if !manifest.photos.iter().any(|p| p.file == file) {
+                    manifest.photos.push(ManifestPhoto::new(file, None));
+                }
+            }
+            manifest.save_to_dir(&new_dir)?;
+            println!("Migrated photos of {} from {} to {}", dir_name, old_dir.display(), new_dir.display());
+            migrated += 1;
+        }
+
+        // Only remove the old folder once nothing is left in it
+        if !old_dir.join(MANIFEST_FILE).exists() && std::fs::read_dir(&old_dir)?.next().is_none() {
+            std::fs::remove_dir(&old_dir)?;
+        }
+    }
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::ImageResult;
+    use crate::storage::LocalStorage;
+
+    fn scored_manifest() -> PhotoManifest {
+        let mut manifest = PhotoManifest::new("ChIJplace", "Corner Bar");
+        let mut results = HashMap::new();
+        for (index, confidence) in [0.9, 0.7, 0.8].iter().enumerate() {
+            let file = format!("ChIJplace_{}.jpg", index);
+            manifest.photos.push(ManifestPhoto::new(file.clone(), Some(format!("places/ChIJplace/photos/{}", index))));
+            let class_name = if index == 2 { "no_pool_table" } else { "pool_table" };
+            results.insert(file.clone(), ImageResult {
+                file,
+                class_name: class_name.to_string(),
+                confidence: *confidence,
+                class_index: None,
+                tile: None,
+            });
+        }
+        manifest.set_results(&results);
+        manifest
+    }
+
+    #[test]
+    fn image_score_is_the_best_positive_photo() {
+        let manifest = scored_manifest();
+        assert_eq!(manifest.image_score(), Some(0.9));
+        assert_eq!(PhotoManifest::new("ChIJplace", "Corner Bar").image_score(), None);
+    }
+
+    #[test]
+    fn rejected_photos_no_longer_count() {
+        let mut manifest = scored_manifest();
+        assert!(manifest.reject("ChIJplace_0.jpg"));
+        assert_eq!(manifest.image_score(), Some(0.7));
+        assert!(manifest.reject("ChIJplace_1.jpg"));
+        assert_eq!(manifest.image_score(), Some(0.0));
+        assert!(!manifest.reject("ChIJplace_9.jpg"));
+
+        // Still listed, so the next scan does not download them again
+        assert!(manifest.find("places/ChIJplace/photos/0").is_some());
+        assert_eq!(manifest.next_file_index(), 3);
+    }
+
+    #[tokio::test]
+    async fn rejections_survive_a_reload() {
+        let dir = std::env::temp_dir().join(format!("racknroll_manifest_{}", std::process::id()));
+        let storage = LocalStorage::new(dir.to_str().unwrap());
+        let mut manifest = scored_manifest();
+        manifest.reject("ChIJplace_0.jpg");
+        manifest.save(&storage).await.unwrap();
+
+        let reloaded = PhotoManifest::load(&storage, "ChIJplace").await.unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(reloaded.photos[0].score, Some(0.0));
+        assert_eq!(reloaded.photos[0].class_name, None);
+        assert_eq!(reloaded.image_score(), Some(0.7));
+    }
+
+    #[test]
+    fn migration_splits_name_keyed_folders_by_place_once() {
+        let output_dir = std::env::temp_dir().join(format!("racknroll_migrate_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let output = output_dir.to_str().unwrap();
+
+        // Two venues called "Corner Bar" shared a folder; a cover image has no place id
+        let old_dir = output_dir.join("Corner Bar");
+        std::fs::create_dir_all(&old_dir).unwrap();
+        for file in ["ChIJaaa_0.jpg", "ChIJaaa_1.jpg", "ChIJbbb_0.png", "cover.jpg"] {
+            std::fs::write(old_dir.join(file), file).unwrap();
+        }
+        std::fs::create_dir_all(output_dir.join(CONFIRMED_DIR)).unwrap();
+        std::fs::write(output_dir.join(CONFIRMED_DIR).join("ChIJccc_0.jpg"), "kept").unwrap();
+
+        assert_eq!(migrate_output_dir(output).unwrap(), 2);
+
+        let first = PhotoManifest::load_from_dir(&venue_dir(output, "ChIJaaa")).unwrap();
+        assert_eq!(first.venue_name, "Corner Bar");
+        let files: Vec<&str> = first.photos.iter().map(|p| p.file.as_str()).collect();
+        assert_eq!(files, vec!["ChIJaaa_0.jpg", "ChIJaaa_1.jpg"]);
+        assert_eq!(std::fs::read_to_string(venue_dir(output, "ChIJaaa").join("ChIJaaa_1.jpg")).unwrap(), "ChIJaaa_1.jpg");
+
+        let second = PhotoManifest::load_from_dir(&venue_dir(output, "ChIJbbb")).unwrap();
+        assert_eq!(second.photos.len(), 1);
+        assert_eq!(second.photos[0].file, "ChIJbbb_0.png");
+
+        // What could not be placed stays where it was, as does the confirmed folder
+        let left: Vec<String> = std::fs::read_dir(&old_dir).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(left, vec!["cover.jpg"]);
+        assert!(output_dir.join(CONFIRMED_DIR).join("ChIJccc_0.jpg").exists());
+        assert!(!output_dir.join("ChIJccc").exists());
+
+        // A second run finds nothing to move and leaves the manifests as they were
+        let manifest_path = venue_dir(output, "ChIJaaa").join(MANIFEST_FILE);
+        let before = std::fs::read_to_string(&manifest_path).unwrap();
+        assert_eq!(migrate_output_dir(output).unwrap(), 0);
+        assert_eq!(std::fs::read_to_string(&manifest_path).unwrap(), before);
+        assert_eq!(PhotoManifest::load_from_dir(&venue_dir(output, "ChIJbbb")).unwrap().photos.len(), 1);
+
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+}
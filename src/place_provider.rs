@@ -0,0 +1,229 @@
+use futures::future::BoxFuture;
+use crate::Config;
+use crate::cost::CostTracker;
+use crate::geometry::{haversine_distance, SearchCircle};
+use crate::google_api::is_fatal_error;
+use crate::google_places_search::{search_field_mask, search_places, search_places_tiled, search_text, Place, PlacesResponse, PlacesSearch};
+use crate::overpass_search::OverpassProvider;
+
+// A source of candidate venues inside a search area.
+// Every provider hands back places in the Google `Place` shape so the rest of the
+// pipeline does not care where a venue came from.
+pub trait PlaceProvider: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn find_places<'a>(
+        &'a self,
+        area: SearchCircle,
+        config: &'a Config,
+        costs: &'a CostTracker,
+    ) -> BoxFuture<'a, Result<PlacesResponse, Box<dyn std::error::Error>>>;
+}
+
+// Google Places: one nearby search per place type plus one text search per keyword
+pub struct GooglePlacesProvider {
+    api_key: String,
+}
+
+impl GooglePlacesProvider {
+    pub fn new(api_key: &str) -> Self {
+        GooglePlacesProvider { api_key: api_key.to_string() }
+    }
+}
+
+impl PlaceProvider for GooglePlacesProvider {
+    fn name(&self) -> &str {
+        "Google Places"
+    }
+
+    fn find_places<'a>(
+        &'a self,
+        area: SearchCircle,
+        config: &'a Config,
+        costs: &'a CostTracker,
+    ) -> BoxFuture<'a, Result<PlacesResponse, Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            let mut all_places = PlacesResponse { places: Vec::new() };
+            let search = PlacesSearch {
+                api_key: &self.api_key,
+                base_url: &config.providers.google_places.base_url,
+                retry: &config.retry,
+                costs,
+                field_mask: search_field_mask(&config.place_fields),
+            };
+
+            for place_type in &config.place_types {
+                let result = if config.coverage.tiled {
+                    search_places_tiled(&search, area, place_type, &config.coverage).await
+                } else {
+                    search_places(
+                        &search,
+                        area.latitude,
+                        area.longitude,
+                        area.radius_meters,
+                        place_type
+                    ).await
+                };
+                match result {
+                    // A bar that is also a restaurant shows up once per type, keep one copy
+                    Ok(places) => all_places.merge(places),
+                    // A bad key or spent quota fails every other type too
+                    Err(e) if is_fatal_error(e.as_ref()) => return Err(e),
+                    Err(e) => eprintln!("Error searching for {}: {}", place_type, e)
+                }
+            }
+
+            for keyword in &config.keywords {
+                match search_text(&search, area, keyword).await {
+                    Ok(places) => all_places.merge(places),
+                    Err(e) if is_fatal_error(e.as_ref()) => return Err(e),
+                    Err(e) => eprintln!("Error searching for \"{}\": {}", keyword, e)
+                }
+            }
+
+            Ok(all_places)
+        })
+    }
+}
+
+// Build the providers switched on in the config, Google first so its place ids win merges
+pub fn enabled_providers(config: &Config, api_key: &str) -> Vec<Box<dyn PlaceProvider>> {
+    let mut providers: Vec<Box<dyn PlaceProvider>> = Vec::new();
+    if config.providers.google {
+        providers.push(Box::new(GooglePlacesProvider::new(api_key)));
+    }
+    if config.providers.overpass.enabled {
+        providers.push(Box::new(OverpassProvider::new(&config.providers.overpass)));
+    }
+    providers
+}
+
+// Words that say what kind of venue it is rather than which one; "Joe's Pub" and
+// "Moe's Pub" share "pub" but are different places
+const GENERIC_NAME_WORDS: [&str; 18] = [
+    "the", "a", "an", "and", "of", "at", "on",
+    "pub", "bar", "grill", "tavern", "inn", "lounge", "saloon", "restaurant", "hotel", "cafe", "club",
+];
+
+// Lowercase, drop possessives, keep letters and digits, split into words
+// ("Joe's Pub & Grill" -> [joe, pub, grill])
+fn name_tokens(name: &str) -> Vec<String> {
+    name.to_lowercase()
+        .replace(['\u{2019}', '`'], "'")
+        .replace("'s ", " ")
+        .trim_end_matches("'s")
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .collect()
+}
+
+// The words that tell venues apart; a name made only of generic words keeps them all
+fn distinctive_tokens(name: &str) -> Vec<String> {
+    let tokens = name_tokens(name);
+    let distinctive: Vec<String> = tokens
+        .iter()
+        .filter(|t| !GENERIC_NAME_WORDS.contains(&t.as_str()))
+        .cloned()
+        .collect();
+    if distinctive.is_empty() { tokens } else { distinctive }
+}
+
+// Two names refer to the same venue if one name's distinctive words are all found in
+// the other ("Kings" vs "Kings Dining & Entertainment") or more than half are shared.
+fn names_match(a: &str, b: &str) -> bool {
+    let a_tokens = distinctive_tokens(a);
+    let b_tokens = distinctive_tokens(b);
+    if a_tokens.is_empty() || b_tokens.is_empty() {
+        return false;
+    }
+
+    let shared = a_tokens.iter().filter(|t| b_tokens.contains(t)).count();
+    let shorter = a_tokens.len().min(b_tokens.len());
+    let longer = a_tokens.len().max(b_tokens.len());
+    shared == shorter || shared * 2 > longer
+}
+
+fn is_same_venue(a: &Place, b: &Place, max_distance_meters: f64) -> bool {
+    a.id == b.id || (
+        haversine_distance(a.location.latitude, a.location.longitude, b.location.latitude, b.location.longitude)
+            <= max_distance_meters
+        && names_match(&a.display_name.text, &b.display_name.text)
+    )
+}
+
+// Fold places from another provider into `all_places`.
+// A place close to an existing one with a matching name is treated as the same venue:
+// the existing entry is kept and only learns the extra `found_by` queries.
+pub fn merge_places(all_places: &mut PlacesResponse, other: PlacesResponse, max_distance_meters: f64) {
+    for place in other.places {
+        match all_places.places.iter_mut().find(|p| is_same_venue(p, &place, max_distance_meters)) {
+            Some(existing) => {
+                for query in place.found_by {
+                    if !existing.found_by.contains(&query) {
+                        existing.found_by.push(query);
+                    }
+                }
+            }
+            None => all_places.places.push(place),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::google_places_search::{DisplayName, Location};
+
+    fn place(id: &str, name: &str, latitude: f64, longitude: f64) -> Place {
+        Place {
+            id: id.to_string(),
+            display_name: DisplayName { text: name.to_string() },
+            location: Location { latitude, longitude },
+            formatted_address: String::new(),
+            types: Vec::new(),
+            business_status: None,
+            website_uri: None,
+            national_phone_number: None,
+            rating: None,
+            price_level: None,
+            regular_opening_hours: None,
+            found_by: vec![id.to_string()],
+        }
+    }
+
+    #[test]
+    fn possessives_and_venue_words_do_not_match_different_venues() {
+        assert!(!names_match("Joe's Pub", "Moe's Pub"));
+        assert!(!names_match("Joe\u{2019}s Bar & Grill", "Moe's Bar & Grill"));
+        assert!(!names_match("The Red Lion", "The Black Lion Inn"));
+    }
+
+    #[test]
+    fn the_same_venue_matches_across_providers() {
+        assert!(names_match("Kings", "Kings Dining & Entertainment"));
+        assert!(names_match("Joe's Pub", "Joe\u{2019}s"));
+        assert!(names_match("The Crown", "Crown Pub"));
+        assert!(names_match("The Pub", "Pub"));
+    }
+
+    #[test]
+    fn name_tokens_drop_possessives() {
+        assert_eq!(name_tokens("Joe's Pub & Grill"), vec!["joe", "pub", "grill"]);
+        assert_eq!(name_tokens("St. Patrick's"), vec!["st", "patrick"]);
+    }
+
+    #[test]
+    fn merge_keeps_nearby_distinct_venues_apart() {
+        let mut all_places = PlacesResponse { places: vec![place("g1", "Joe's Pub", 40.7290, -73.9911)] };
+        let other = PlacesResponse { places: vec![
+            place("osm:node/1", "Moe's Pub", 40.7291, -73.9912),
+            place("osm:node/2", "Joe's Pub", 40.7290, -73.9910),
+        ] };
+        merge_places(&mut all_places, other, 75.0);
+
+        assert_eq!(all_places.places.len(), 2);
+        assert_eq!(all_places.places[0].found_by, vec!["g1", "osm:node/2"]);
+        assert_eq!(all_places.places[1].id, "osm:node/1");
+    }
+}
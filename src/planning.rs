@@ -0,0 +1,494 @@
+use chrono::{DateTime, Duration, Utc};
+use futures::future::BoxFuture;
+use serde::{Serialize, Deserialize};
+use std::path::Path;
+use crate::Config;
+use crate::cost::{CostTracker, Sku};
+use crate::google_api::is_fatal_error;
+use crate::google_places_search::{Place, PlacesResponse};
+use crate::models::VenueCollection;
+use crate::overpass_search::OSM_ID_PREFIX;
+use crate::place_provider::{enabled_providers, merge_places};
+use crate::search_area::SearchArea;
+use crate::storage::sha256_hex;
+
+// Discovery caching and the numbers a dry run reports
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PlanningSettings {
+    pub discovery_cache_path: String,
+    // Reuse a cached discovery of the same search for this many hours, e.g. a dry run's
+    // discovery in the real run that follows it. 0, the default, always searches again.
+    pub discovery_cache_hours: i64,
+    // Photos expected per venue when estimating downloads (Google hands out at most 10)
+    pub photos_per_venue: usize,
+}
+
+impl Default for PlanningSettings {
+    fn default() -> Self {
+        PlanningSettings {
+            discovery_cache_path: "discovery_cache.json".to_string(),
+            discovery_cache_hours: 0,
+            photos_per_venue: 10,
+        }
+    }
+}
+
+// The candidate places for a search, before any photos are fetched
+pub struct Discovery {
+    pub places: PlacesResponse,
+    pub from_cache: bool,
+    // The budget ran out part way through, so `places` is incomplete
+    pub budget_reached: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedDiscovery {
+    key: String,
+    discovered_at: DateTime<Utc>,
+    places: Vec<Place>,
+}
+
+// Everything that changes which places discovery returns. A GeoJSON file is keyed by
+// its contents as well as its path, so editing the area searches it again.
+fn discovery_key(config: &Config) -> String {
+    let geojson_file_sha256 = config.area
+        .as_ref()
+        .and_then(|area| area.geojson_file.as_ref())
+        .and_then(|path| std::fs::read(path).ok())
+        .map(|content| sha256_hex(&content));
+    serde_json::json!({
+        "location": config.location,
+        "area": config.area,
+        "geojson_file_sha256": geojson_file_sha256,
+        "place_types": config.place_types,
+        "keywords": config.keywords,
+        "coverage": config.coverage,
+        "providers": config.providers,
+        "place_fields": config.place_fields,
+    }).to_string()
+}
+
+fn load_cache(path: &str) -> Vec<CachedDiscovery> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn load_cached_discovery(settings: &PlanningSettings, key: &str) -> Option<CachedDiscovery> {
+    if settings.discovery_cache_hours <= 0 {
+        return None;
+    }
+    let oldest = Utc::now() - Duration::hours(settings.discovery_cache_hours);
+    load_cache(&settings.discovery_cache_path)
+        .into_iter()
+        .find(|entry| entry.key == key && entry.discovered_at > oldest)
+}
+
+// Store a discovery, replacing older results for the same search and dropping expired ones
+fn save_cached_discovery(settings: &PlanningSettings, key: &str, places: &[Place]) -> Result<(), Box<dyn std::error::Error>> {
+    let oldest = Utc::now() - Duration::hours(settings.discovery_cache_hours);
+    let mut entries = load_cache(&settings.discovery_cache_path);
+    entries.retain(|entry| entry.key != key && entry.discovered_at > oldest);
+    entries.push(CachedDiscovery {
+        key: key.to_string(),
+        discovered_at: Utc::now(),
+        places: places.to_vec(),
+    });
+    std::fs::write(&settings.discovery_cache_path, serde_json::to_string(&entries)?)?;
+    Ok(())
+}
+
+// Ask every enabled provider for places in the search area and merge what they find.
+// A recent discovery of the same search is reused from the cache instead.
+pub async fn discover_places<F>(
+    config: &Config,
+    search_area: &SearchArea,
+    api_key: &str,
+    costs: &CostTracker,
+    status_callback: &Option<F>,
+) -> Result<Discovery, Box<dyn std::error::Error>>
+where
+    F: Fn(&str) -> BoxFuture<'static, ()>,
+{
+    let key = discovery_key(config);
+    if let Some(cached) = load_cached_discovery(&config.planning, &key) {
+        if let Some(callback) = status_callback {
+            callback(&format!("Reusing {} places discovered at {}",
+                cached.places.len(), cached.discovered_at.format("%Y-%m-%d %H:%M UTC"))).await;
+        }
+        return Ok(Discovery {
+            places: PlacesResponse { places: cached.places },
+            from_cache: true,
+            budget_reached: false,
+        });
+    }
+
+    let discovery_circles = search_area.discovery_circles(config.coverage.area_cell_radius_meters);
+    if let Some(callback) = status_callback {
+        callback(&format!("Searching {} area cells", discovery_circles.len())).await;
+    }
+
+    let mut all_places = PlacesResponse { places: Vec::new() };
+    let mut budget_reached = false;
+    for provider in enabled_providers(config, api_key) {
+        let mut provider_places = PlacesResponse { places: Vec::new() };
+        for circle in &discovery_circles {
+            if costs.budget_exhausted() {
+                budget_reached = true;
+                break;
+            }
+            match provider.find_places(*circle, config, costs).await {
+                Ok(places) => provider_places.merge(places),
+                Err(e) if is_fatal_error(e.as_ref()) => return Err(e),
+                Err(e) => eprintln!("Error searching {}: {}", provider.name(), e)
+            }
+        }
+        // Cells poke out past the edges of a polygon, keep only what is inside
+        provider_places.places.retain(|p| search_area.contains(p.location.latitude, p.location.longitude));
+
+        if let Some(callback) = status_callback {
+            callback(&format!("{} found {} places", provider.name(), provider_places.places.len())).await;
+        }
+        merge_places(&mut all_places, provider_places, config.providers.merge_distance_meters);
+        if budget_reached {
+            break;
+        }
+    }
+
+    if let Some(callback) = status_callback {
+        callback(&format!("Found {} unique places", all_places.places.len())).await;
+    }
+
+    // The tiled and text searches stop paging once the budget runs out, so a budget
+    // spent during the last cell leaves the discovery incomplete too
+    let budget_reached = budget_reached || costs.budget_exhausted();
+
+    // A partial discovery would hide venues from the next run, so only cache complete ones
+    if !budget_reached && config.planning.discovery_cache_hours > 0 {
+        if let Err(e) = save_cached_discovery(&config.planning, &key, &all_places.places) {
+            eprintln!("Error saving discovery cache: {}", e);
+        }
+    }
+
+    Ok(Discovery {
+        places: all_places,
+        from_cache: false,
+        budget_reached,
+    })
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VenueState {
+    // Not in the database yet
+    New,
+    // In the database but older than months_threshold
+    Stale,
+    // In the database and recent enough to skip
+    Fresh,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlannedVenue {
+    pub name: String,
+    pub place_id: String,
+    pub address: String,
+    pub state: VenueState,
+    // A real run would download and classify photos for this venue
+    pub needs_photos: bool,
+}
+
+// What a real run over the same search would do
+#[derive(Serialize, Debug)]
+pub struct SearchPlan {
+    pub discovered: usize,
+    pub discovery_from_cache: bool,
+    pub new_venues: usize,
+    pub stale_venues: usize,
+    pub fresh_venues: usize,
+    // OpenStreetMap-only places, handled without any Google calls
+    pub osm_only_venues: usize,
+    pub venues_to_process: usize,
+    pub estimated_photos: usize,
+    pub inference_runs: usize,
+    // Spent by this dry run on discovery; with discovery_cache_hours set, the real run
+    // reuses the cached result instead of paying again
+    pub discovery_cost: f64,
+    pub estimated_cost: f64,
+    pub budget: Option<f64>,
+    pub venues: Vec<PlannedVenue>,
+}
+
+impl SearchPlan {
+    fn build(config: &Config, discovery: Discovery, collection: &VenueCollection, discovery_cost: f64) -> Self {
+        let mut plan = SearchPlan {
+            discovered: discovery.places.places.len(),
+            discovery_from_cache: discovery.from_cache,
+            new_venues: 0,
+            stale_venues: 0,
+            fresh_venues: 0,
+            osm_only_venues: 0,
+            venues_to_process: 0,
+            estimated_photos: 0,
+            inference_runs: 0,
+            discovery_cost,
+            estimated_cost: 0.0,
+            budget: config.budget,
+            venues: Vec::new(),
+        };
+
+        for place in discovery.places.places {
+            let known = collection.venues.iter().any(|v| v.place_id == place.id);
+            let (should_process, _) = collection.should_process_venue(&place.id, config.processing.months_threshold);
+            let state = match (known, should_process) {
+                (false, _) => VenueState::New,
+                (true, true) => VenueState::Stale,
+                (true, false) => VenueState::Fresh,
+            };
+            match state {
+                VenueState::New => plan.new_venues += 1,
+                VenueState::Stale => plan.stale_venues += 1,
+                VenueState::Fresh => plan.fresh_venues += 1,
+            }
+
+            let osm_only = place.id.starts_with(OSM_ID_PREFIX);
+            if osm_only {
+                plan.osm_only_venues += 1;
+            }
+            let skipped_closed = config.processing.skip_permanently_closed && place.is_permanently_closed();
+            let needs_photos = !osm_only
+                && !skipped_closed
+                && (state != VenueState::Fresh || config.processing.reprocess_all);
+            if needs_photos {
+                plan.venues_to_process += 1;
+            }
+
+            plan.venues.push(PlannedVenue {
+                name: place.display_name.text,
+                place_id: place.id,
+                address: place.formatted_address,
+                state,
+                needs_photos,
+            });
+        }
+
+        // Early stopping caps the photos per venue; the estimate stays an upper bound
+        let mut photos_per_venue = config.planning.photos_per_venue;
+        if config.early_stop.enabled && config.early_stop.max_photos_per_venue > 0 {
+            photos_per_venue = photos_per_venue.min(config.early_stop.max_photos_per_venue);
+        }
+        plan.estimated_photos = plan.venues_to_process * photos_per_venue;
+        // One classifier run per venue folder
+        plan.inference_runs = plan.venues_to_process;
+        plan.estimated_cost = plan.venues_to_process as f64 * config.prices.price_per_call(Sku::PlaceDetails)
+            + plan.estimated_photos as f64 * config.prices.price_per_call(Sku::PlacePhoto);
+        plan
+    }
+}
+
+impl std::fmt::Display for SearchPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Search plan")?;
+        if self.discovery_from_cache {
+            writeln!(f, "  {} places found (cached discovery)", self.discovered)?;
+        } else {
+            writeln!(f, "  {} places found (discovery cost ${:.2})", self.discovered, self.discovery_cost)?;
+        }
+        writeln!(f, "  {} new, {} stale, {} fresh ({} OpenStreetMap only)",
+            self.new_venues, self.stale_venues, self.fresh_venues, self.osm_only_venues)?;
+        writeln!(f, "  {} venues to process: ~{} photo downloads, {} inference runs",
+            self.venues_to_process, self.estimated_photos, self.inference_runs)?;
+        write!(f, "  Estimated cost: ${:.2}", self.estimated_cost)?;
+        if let Some(budget) = self.budget {
+            write!(f, " (budget ${:.2})", budget)?;
+            if self.estimated_cost > budget && self.venues_to_process > 0 {
+                let per_venue = self.estimated_cost / self.venues_to_process as f64;
+                write!(f, "\n  The budget would stop the run after about {} venues", (budget / per_venue).floor())?;
+            }
+        }
+        for venue in self.venues.iter().filter(|v| v.needs_photos) {
+            let state = match venue.state {
+                VenueState::New => "new",
+                VenueState::Stale => "stale",
+                VenueState::Fresh => "fresh",
+            };
+            write!(f, "\n  {:<6} {} ({})", state, venue.name, venue.address)?;
+        }
+        Ok(())
+    }
+}
+
+// Dry run: discover (or reuse cached) places and work out what a real run would cost,
+// without downloading or classifying any photos
+pub async fn plan_search<F>(
+    mut config: Config,
+    api_key: &str,
+    costs: &CostTracker,
+    status_callback: Option<F>,
+) -> Result<SearchPlan, Box<dyn std::error::Error>>
+where
+    F: Fn(&str) -> BoxFuture<'static, ()>,
+{
+    if let Some(geocoded) = config.resolve_location(api_key, costs).await? {
+        if let Some(callback) = &status_callback {
+            callback(&format!("Resolved \"{}\" to {} ({}, {}) via {}",
+                geocoded.query, geocoded.matched_name, geocoded.latitude, geocoded.longitude, geocoded.source)).await;
+        }
+    }
+    let search_area = config.search_area()?;
+
+    let discovery = discover_places(&config, &search_area, api_key, costs, &status_callback).await?;
+    if discovery.budget_reached {
+        if let Some(callback) = &status_callback {
+            callback("Budget reached during discovery, the plan only covers part of the area").await;
+        }
+    }
+
+    let collection = VenueCollection::load_from_json(Path::new("venues_database.json"))
+        .unwrap_or_else(|_| VenueCollection::new());
+    let plan = SearchPlan::build(&config, discovery, &collection, costs.estimated_cost());
+
+    if let Some(callback) = &status_callback {
+        callback(&format!("Plan: {} venues to process, ~{} photos, estimated ${:.2}",
+            plan.venues_to_process, plan.estimated_photos, plan.estimated_cost)).await;
+    }
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cost::PriceTable;
+    use crate::models::Venue;
+
+    fn config(yaml: &str) -> Config {
+        let base = "processing: {months_threshold: 6, reprocess_all: false, save_negative_images: false}\nplace_types: [bar]\n";
+        serde_yaml::from_str(&format!("{}{}", base, yaml)).unwrap()
+    }
+
+    fn scratch_file(name: &str) -> String {
+        std::env::temp_dir().join(format!("racknroll_planning_{}_{}", std::process::id(), name)).display().to_string()
+    }
+
+    #[test]
+    fn editing_the_geojson_file_changes_the_discovery_key() {
+        let path = scratch_file("area.geojson");
+        let config = config(&format!("area: {{geojson_file: {}}}\n", path));
+        std::fs::write(&path, r#"{"type": "Polygon", "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 0]]]}"#).unwrap();
+        let before = discovery_key(&config);
+        assert_eq!(discovery_key(&config), before);
+
+        std::fs::write(&path, r#"{"type": "Polygon", "coordinates": [[[0, 0], [2, 0], [2, 2], [0, 0]]]}"#).unwrap();
+        assert_ne!(discovery_key(&config), before);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn the_discovery_key_follows_what_discovery_searches() {
+        let base = config("location: {latitude: 40.0, longitude: -75.0, radius_meters: 1000.0}\n");
+        let key = discovery_key(&base);
+
+        // Settings that only affect photos, scoring or spend reuse the discovery
+        let mut unrelated = base.clone();
+        unrelated.budget = Some(1.0);
+        unrelated.processing.months_threshold = 1;
+        unrelated.planning.photos_per_venue = 3;
+        assert_eq!(discovery_key(&unrelated), key);
+
+        let mut other_types = base.clone();
+        other_types.place_types.push("pub".to_string());
+        assert_ne!(discovery_key(&other_types), key);
+
+        let mut tiled = base.clone();
+        tiled.coverage.tiled = true;
+        assert_ne!(discovery_key(&tiled), key);
+
+        let moved = config("location: {latitude: 40.1, longitude: -75.0, radius_meters: 1000.0}\n");
+        assert_ne!(discovery_key(&moved), key);
+    }
+
+    fn place(id: &str) -> Place {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "displayName": { "text": id },
+            "location": { "latitude": 40.0, "longitude": -75.0 },
+            "formattedAddress": "1 Main Street",
+        })).unwrap()
+    }
+
+    fn cached(key: &str, hours_ago: i64) -> CachedDiscovery {
+        CachedDiscovery {
+            key: key.to_string(),
+            discovered_at: Utc::now() - Duration::hours(hours_ago),
+            places: vec![place(key)],
+        }
+    }
+
+    #[test]
+    fn cached_discoveries_expire() {
+        let settings = PlanningSettings {
+            discovery_cache_path: scratch_file("expiry_cache.json"),
+            discovery_cache_hours: 24,
+            ..PlanningSettings::default()
+        };
+        let entries = vec![cached("recent", 23), cached("expired", 25)];
+        std::fs::write(&settings.discovery_cache_path, serde_json::to_string(&entries).unwrap()).unwrap();
+
+        assert_eq!(load_cached_discovery(&settings, "recent").unwrap().places[0].id, "recent");
+        assert!(load_cached_discovery(&settings, "expired").is_none());
+        assert!(load_cached_discovery(&settings, "never searched").is_none());
+
+        // Saving replaces the old result for the key and drops expired entries
+        save_cached_discovery(&settings, "recent", &[place("a"), place("b")]).unwrap();
+        let keys: Vec<String> = load_cache(&settings.discovery_cache_path).into_iter().map(|e| e.key).collect();
+        assert_eq!(keys, vec!["recent"]);
+        assert_eq!(load_cached_discovery(&settings, "recent").unwrap().places.len(), 2);
+
+        // The default of 0 hours never reuses anything, however fresh
+        let off = PlanningSettings { discovery_cache_hours: 0, ..settings.clone() };
+        assert!(load_cached_discovery(&off, "recent").is_none());
+        std::fs::remove_file(&settings.discovery_cache_path).unwrap();
+    }
+
+    fn discovery(ids: &[&str]) -> Discovery {
+        Discovery {
+            places: PlacesResponse { places: ids.iter().map(|id| place(id)).collect() },
+            from_cache: false,
+            budget_reached: false,
+        }
+    }
+
+    #[test]
+    fn the_plan_prices_details_and_photos_for_venues_needing_them() {
+        let config = config("prices: {place_details: 20.0, place_photo: 5.0}\nplanning: {photos_per_venue: 10}\n");
+        let mut collection = VenueCollection::new();
+        let fresh = Venue::new("fresh".into(), "fresh".into(), String::new(), 0.5, 40.0, -75.0);
+        let mut stale = Venue::new("stale".into(), "stale".into(), String::new(), 0.5, 40.0, -75.0);
+        stale.processed_date = Utc::now() - Duration::days(365);
+        collection.add_venue(fresh);
+        collection.add_venue(stale);
+
+        let osm_only = format!("{}node/1", OSM_ID_PREFIX);
+        let plan = SearchPlan::build(&config, discovery(&["new", "fresh", "stale", &osm_only]), &collection, 0.07);
+        assert_eq!((plan.new_venues, plan.stale_venues, plan.fresh_venues, plan.osm_only_venues), (2, 1, 1, 1));
+
+        // "new" and "stale": a details call and ten photos each
+        assert_eq!(plan.venues_to_process, 2);
+        assert_eq!(plan.estimated_photos, 20);
+        assert_eq!(plan.inference_runs, 2);
+        assert!((plan.estimated_cost - (2.0 * 0.020 + 20.0 * 0.005)).abs() < 1e-12, "{}", plan.estimated_cost);
+        assert_eq!(plan.discovery_cost, 0.07);
+    }
+
+    #[test]
+    fn early_stopping_caps_the_photo_estimate() {
+        let config = config("early_stop: {enabled: true, max_photos_per_venue: 4}\n");
+        let plan = SearchPlan::build(&config, discovery(&["a", "b", "c"]), &VenueCollection::new(), 0.0);
+        assert_eq!(plan.estimated_photos, 12);
+        let prices = PriceTable::default();
+        let expected = 3.0 * prices.price_per_call(Sku::PlaceDetails) + 12.0 * prices.price_per_call(Sku::PlacePhoto);
+        assert!((plan.estimated_cost - expected).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,360 @@
+use lazy_static::lazy_static;
+use serde::{Serialize, Deserialize};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::classifier::{ImageResult, Inference};
+
+// PoolTableInference.py started once with --worker and kept running, so the YOLO
+// weights load once instead of once per venue. Requests and results are JSON lines
+// on its stdin and stdout; see `serve` in the script for the protocol.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PythonWorkerSettings {
+    pub enabled: bool,
+    // Time allowed to start Python and load the weights
+    pub startup_timeout_seconds: u64,
+    // Time allowed for each photo's result; a worker that goes quiet longer is restarted
+    pub image_timeout_seconds: u64,
+    // Restarts after a crash or timeout before a venue's photos are given up on
+    pub max_restarts: usize,
+}
+
+impl Default for PythonWorkerSettings {
+    fn default() -> Self {
+        PythonWorkerSettings {
+            enabled: false,
+            startup_timeout_seconds: 120,
+            image_timeout_seconds: 60,
+            max_restarts: 2,
+        }
+    }
+}
+
+// The script started as the worker, from the working directory
+const WORKER_SCRIPT: &str = "PoolTableInference.py";
+
+lazy_static! {
+    // One worker per process, shared by every venue; requests take turns
+    static ref WORKER: Mutex<Option<PythonWorker>> = Mutex::new(None);
+}
+
+#[derive(Serialize)]
+struct WorkerRequest<'a> {
+    id: u64,
+    images: &'a [PathBuf],
+    output_dir: &'a Path,
+    save_negative: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WorkerMessage {
+    Ready,
+    Image {
+        id: u64,
+        #[serde(flatten)]
+        result: ImageResult,
+    },
+    Done {
+        id: u64,
+    },
+    Error {
+        id: Option<u64>,
+        message: String,
+    },
+}
+
+enum WorkerError {
+    // The script reported an error for the request; the worker itself is fine
+    Request(String),
+    // The worker died, timed out or spoke nonsense and has to be restarted
+    Crashed(String),
+}
+
+struct PythonWorker {
+    script: PathBuf,
+    model_path: String,
+    child: Child,
+    stdin: ChildStdin,
+    // stdout lines, read on their own thread so waiting for them can time out
+    lines: Receiver<String>,
+    next_id: u64,
+}
+
+impl PythonWorker {
+    fn start(script: &Path, model_path: &str, settings: &PythonWorkerSettings) -> Result<Self, Box<dyn std::error::Error>> {
+        println!("Starting Python inference worker for {}", model_path);
+        let mut child = Command::new("python3")
+            .arg(script)
+            .arg("--worker")
+            .arg("-m")
+            .arg(model_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or("Python worker has no stdin")?;
+        let stdout = child.stdout.take().ok_or("Python worker has no stdout")?;
+
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) => if sender.send(line).is_err() { break },
+                    Err(_) => break,
+                }
+            }
+        });
+
+        let mut worker = PythonWorker {
+            script: script.to_path_buf(),
+            model_path: model_path.to_string(),
+            child,
+            stdin,
+            lines,
+            next_id: 0,
+        };
+        match worker.next_message(Duration::from_secs(settings.startup_timeout_seconds)) {
+            Ok(WorkerMessage::Ready) => Ok(worker),
+            Ok(_) => Err("Python worker did not start with a ready message".into()),
+            Err(WorkerError::Request(message)) | Err(WorkerError::Crashed(message)) =>
+                Err(format!("Python worker did not start: {}", message).into()),
+        }
+    }
+
+    fn next_message(&mut self, timeout: Duration) -> Result<WorkerMessage, WorkerError> {
+        loop {
+            let line = match self.lines.recv_timeout(timeout) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) =>
+                    return Err(WorkerError::Crashed(format!("no answer within {}s", timeout.as_secs()))),
+                Err(RecvTimeoutError::Disconnected) => {
+                    let status = self.child.wait().map(|s| s.to_string()).unwrap_or_default();
+                    return Err(WorkerError::Crashed(format!("worker exited {}", status)));
+                },
+            };
+            match serde_json::from_str(&line) {
+                Ok(message) => return Ok(message),
+                // A library printing straight to stdout; not part of the protocol
+                Err(_) => println!("Python worker: {}", line),
+            }
+        }
+    }
+
+    // Send one venue's photos; results are recorded as they arrive, so a crash halfway
+    // keeps the photos already answered
+    fn classify(
+        &mut self,
+        image_paths: &[PathBuf],
+        output_dir: &Path,
+        save_negative: bool,
+        timeout: Duration,
+        inference: &mut Inference,
+    ) -> Result<(), WorkerError> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let request = WorkerRequest { id, images: image_paths, output_dir, save_negative };
+        let line = serde_json::to_string(&request).map_err(|e| WorkerError::Request(e.to_string()))?;
+        writeln!(self.stdin, "{}", line)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| WorkerError::Crashed(format!("could not send request: {}", e)))?;
+
+        loop {
+            match self.next_message(timeout)? {
+                WorkerMessage::Image { id: answer, result } if answer == id => inference.record(result),
+                WorkerMessage::Done { id: answer } if answer == id => return Ok(()),
+                WorkerMessage::Error { id: Some(answer), message } if answer == id => return Err(WorkerError::Request(message)),
+                WorkerMessage::Error { id: None, message } => return Err(WorkerError::Crashed(message)),
+                // Left over from an earlier request
+                _ => {},
+            }
+        }
+    }
+}
+
+impl Drop for PythonWorker {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Classify photos with the shared worker, starting it on first use and restarting it
+// after a crash or timeout. Photos answered before a crash are not sent again.
+pub fn run_python_worker(
+    image_paths: &[PathBuf],
+    model_path: &str,
+    output_dir: &Path,
+    save_negative: bool,
+    settings: &PythonWorkerSettings,
+) -> Result<Inference, Box<dyn std::error::Error>> {
+    run_worker_script(Path::new(WORKER_SCRIPT), image_paths, model_path, output_dir, save_negative, settings)
+}
+
+fn run_worker_script(
+    script: &Path,
+    image_paths: &[PathBuf],
+    model_path: &str,
+    output_dir: &Path,
+    save_negative: bool,
+    settings: &PythonWorkerSettings,
+) -> Result<Inference, Box<dyn std::error::Error>> {
+    let mut worker = WORKER.lock().map_err(|_| "Python worker lock poisoned")?;
+    let timeout = Duration::from_secs(settings.image_timeout_seconds);
+    let mut inference = Inference::default();
+    let mut restarts = 0;
+    loop {
+        let pending: Vec<PathBuf> = image_paths
+            .iter()
+            .filter(|path| {
+                let file = path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+                !inference.images.contains_key(&file)
+            })
+            .cloned()
+            .collect();
+        if pending.is_empty() {
+            return Ok(inference);
+        }
+
+        if worker.as_ref().map(|w| w.model_path != model_path || w.script != script).unwrap_or(true) {
+            *worker = None;
+            *worker = Some(PythonWorker::start(script, model_path, settings)?);
+        }
+        let running = worker.as_mut().ok_or("Python worker did not start")?;
+        match running.classify(&pending, output_dir, save_negative, timeout, &mut inference) {
+            Ok(()) => return Ok(inference),
+            Err(WorkerError::Request(message)) => return Err(message.into()),
+            Err(WorkerError::Crashed(message)) => {
+                *worker = None;
+                if restarts >= settings.max_restarts {
+                    return Err(format!("Python worker failed: {}", message).into());
+                }
+                restarts += 1;
+                eprintln!("Python worker failed ({}), restarting ({}/{})", message, restarts, settings.max_restarts);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Speaks the worker protocol. The model path picks the behaviour, "mode:state",
+    // and every request's images are logged to state.log.
+    const STUB_WORKER: &str = r#"
+import json, os, sys, time
+mode, _, state = sys.argv[sys.argv.index("-m") + 1].partition(":")
+print("loading weights, not a protocol line")
+print(json.dumps({"type": "ready"}), flush=True)
+for line in sys.stdin:
+    request = json.loads(line)
+    id = request["id"]
+    with open(state + ".log", "a") as log:
+        log.write(json.dumps(request["images"]) + "\n")
+    if mode == "hang":
+        time.sleep(60)
+    if mode == "reject":
+        print(json.dumps({"type": "error", "id": id, "message": "bad photo"}), flush=True)
+        continue
+    if mode == "stale":
+        old = id + 100
+        print(json.dumps({"type": "image", "id": old, "file": "stale.jpg", "class_name": "pool_table", "confidence": 1.0}))
+        print(json.dumps({"type": "error", "id": old, "message": "old request"}))
+        print(json.dumps({"type": "done", "id": old}), flush=True)
+    for n, path in enumerate(request["images"]):
+        if mode == "crash_once" and n == 1 and not os.path.exists(state):
+            open(state, "w").close()
+            sys.exit(1)
+        print(json.dumps({"type": "image", "id": id, "file": os.path.basename(path),
+                          "class_name": "pool_table", "confidence": 0.5 + 0.1 * n}), flush=True)
+    print(json.dumps({"type": "done", "id": id}), flush=True)
+"#;
+
+    // The stub script and a state path, both unique to the test
+    fn stub(name: &str) -> (PathBuf, String) {
+        let dir = std::env::temp_dir().join(format!("racknroll_worker_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("stub_worker.py");
+        std::fs::write(&script, STUB_WORKER).unwrap();
+        (script, dir.join("state").display().to_string())
+    }
+
+    fn settings(image_timeout_seconds: u64) -> PythonWorkerSettings {
+        PythonWorkerSettings { enabled: true, startup_timeout_seconds: 30, image_timeout_seconds, max_restarts: 1 }
+    }
+
+    fn photos() -> Vec<PathBuf> {
+        vec![PathBuf::from("venue/a.jpg"), PathBuf::from("venue/b.jpg"), PathBuf::from("venue/c.jpg")]
+    }
+
+    fn requests(state: &str) -> Vec<Vec<String>> {
+        std::fs::read_to_string(format!("{}.log", state)).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn files(inference: &Inference) -> Vec<String> {
+        let mut files: Vec<String> = inference.images.keys().cloned().collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn answers_to_other_requests_are_ignored() {
+        let (script, state) = stub("stale");
+        let model = format!("stale:{}", state);
+        for _ in 0..2 {
+            let inference = run_worker_script(&script, &photos(), &model, Path::new("venue"), false, &settings(30)).unwrap();
+            assert_eq!(files(&inference), vec!["a.jpg", "b.jpg", "c.jpg"]);
+            assert!((inference.probability - 0.7).abs() < 1e-6);
+        }
+        // Both venues went to the one worker
+        assert_eq!(requests(&state).len(), 2);
+        std::fs::remove_dir_all(script.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn a_crashed_worker_is_restarted_for_the_unanswered_photos() {
+        let (script, state) = stub("crash");
+        let model = format!("crash_once:{}", state);
+        let inference = run_worker_script(&script, &photos(), &model, Path::new("venue"), false, &settings(30)).unwrap();
+        assert_eq!(files(&inference), vec!["a.jpg", "b.jpg", "c.jpg"]);
+
+        // a.jpg was answered before the crash and is not sent again
+        let sent = requests(&state);
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].len(), 3);
+        assert_eq!(sent[1], vec!["venue/b.jpg", "venue/c.jpg"]);
+        std::fs::remove_dir_all(script.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn a_silent_worker_times_out_and_gives_up_after_its_restarts() {
+        let (script, state) = stub("hang");
+        let model = format!("hang:{}", state);
+        let started = std::time::Instant::now();
+        let error = run_worker_script(&script, &photos(), &model, Path::new("venue"), false, &settings(1)).unwrap_err();
+        assert!(error.to_string().contains("no answer within 1s"), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(30));
+        // The first try and one restart
+        assert_eq!(requests(&state).len(), 2);
+        std::fs::remove_dir_all(script.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn a_request_error_is_not_retried() {
+        let (script, state) = stub("reject");
+        let model = format!("reject:{}", state);
+        let error = run_worker_script(&script, &photos(), &model, Path::new("venue"), false, &settings(30)).unwrap_err();
+        assert_eq!(error.to_string(), "bad photo");
+        assert_eq!(requests(&state).len(), 1);
+        std::fs::remove_dir_all(script.parent().unwrap()).unwrap();
+    }
+}
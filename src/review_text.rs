@@ -0,0 +1,221 @@
+// Finds pool-table mentions in review text.
+// "Pool" and "table" are everywhere in reviews ("lounged by the pool", "got a table
+// right away"), so only whole phrases count, and the ones built on a bare "pool"
+// are discounted when the same sentence is about swimming. A phrase with a negation
+// right next to it ("no pool table anymore", "they removed the pool table") does not
+// count; one further away ("two pool tables and no cover") is about something else.
+
+// How strongly a phrase points at a pool table. `ambiguous` phrases could also be
+// about a swimming pool and lose most of their weight in a swimming sentence.
+struct Phrase {
+    words: &'static [&'static str],
+    weight: f32,
+    ambiguous: bool,
+}
+
+const POOL_PHRASES: &[Phrase] = &[
+    Phrase { words: &["pool", "table"], weight: 0.9, ambiguous: false },
+    Phrase { words: &["billiard"], weight: 0.9, ambiguous: false },
+    Phrase { words: &["snooker"], weight: 0.8, ambiguous: false },
+    Phrase { words: &["pool", "hall"], weight: 0.8, ambiguous: false },
+    Phrase { words: &["pool", "cue"], weight: 0.8, ambiguous: false },
+    Phrase { words: &["shoot", "some", "stick"], weight: 0.8, ambiguous: false },
+    Phrase { words: &["shoot", "stick"], weight: 0.8, ambiguous: false },
+    Phrase { words: &["8", "ball"], weight: 0.6, ambiguous: false },
+    Phrase { words: &["eight", "ball"], weight: 0.6, ambiguous: false },
+    Phrase { words: &["9", "ball"], weight: 0.6, ambiguous: false },
+    Phrase { words: &["played", "pool"], weight: 0.8, ambiguous: true },
+    Phrase { words: &["play", "pool"], weight: 0.8, ambiguous: true },
+    Phrase { words: &["playing", "pool"], weight: 0.8, ambiguous: true },
+    Phrase { words: &["shoot", "pool"], weight: 0.8, ambiguous: true },
+    Phrase { words: &["shooting", "pool"], weight: 0.8, ambiguous: true },
+    Phrase { words: &["shot", "pool"], weight: 0.8, ambiguous: true },
+    Phrase { words: &["game", "of", "pool"], weight: 0.8, ambiguous: true },
+    Phrase { words: &["pool", "league"], weight: 0.7, ambiguous: true },
+];
+
+// Words that put a sentence about a swimming pool. Only words that are about
+// swimming; "indoor", "rooftop" or "heated" describe pool halls just as well.
+const SWIMMING_WORDS: &[&str] = &[
+    "swim", "swimming", "swam", "swimsuit", "poolside", "lifeguard", "lounger", "jacuzzi",
+    "hot tub", "infinity", "kiddie", "splash", "towel", "diving",
+];
+
+// Phrases that say a pool table is not (or no longer) there when they come just
+// before it. "don't" and friends split into "don" and "t", so the stem is enough.
+const NEGATIONS: &[&[&str]] = &[
+    &["no"], &["not"], &["never"], &["without"], &["none"],
+    &["removed"], &["rid"], &["took", "out"], &["replaced"],
+    &["don"], &["doesn"], &["didn"], &["isn"], &["wasn"], &["aren"], &["weren"], &["hasn"], &["haven"],
+];
+
+// The same when they come just after it: "the pool tables are gone"
+const TRAILING_NEGATIONS: &[&[&str]] = &[
+    &["anymore"], &["gone"], &["removed"], &["replaced"], &["taken", "out"],
+];
+
+// How many words either side of a phrase a negation may sit and still apply to it
+const NEGATION_WINDOW: usize = 3;
+
+// What an ambiguous phrase is worth next to swimming words
+const SWIMMING_DISCOUNT: f32 = 0.1;
+
+// Longest snippet kept on the venue
+const MAX_SNIPPET_CHARS: usize = 240;
+
+#[derive(Debug, Default)]
+pub struct TextEvidence {
+    // Chance the texts describe a pool table, combining every matching sentence
+    pub score: f32,
+    // The sentences that matched
+    pub snippets: Vec<String>,
+}
+
+fn words(sentence: &str) -> Vec<String> {
+    sentence
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_string())
+        .collect()
+}
+
+// "table" also matches "tables", "billiard" matches "billiards"
+fn word_matches(word: &str, expected: &str) -> bool {
+    word == expected || word.strip_suffix('s') == Some(expected)
+}
+
+fn phrase_at(words: &[String], start: usize, phrase: &[&str]) -> bool {
+    words.get(start..start + phrase.len()).is_some_and(|window| {
+        window.iter().zip(phrase).all(|(word, expected)| word_matches(word, expected))
+    })
+}
+
+fn contains_phrase(words: &[String], phrase: &[&str]) -> bool {
+    (0..words.len()).any(|start| phrase_at(words, start, phrase))
+}
+
+// A negation ending in the few words before `start`, or starting in the few after `end`
+fn negated(words: &[String], start: usize, end: usize) -> bool {
+    let before = NEGATIONS.iter().any(|negation| {
+        (start.saturating_sub(NEGATION_WINDOW)..start)
+            .any(|at| at + negation.len() <= start && phrase_at(words, at, negation))
+    });
+    let after = TRAILING_NEGATIONS.iter().any(|negation| {
+        (end..end + NEGATION_WINDOW).any(|at| phrase_at(words, at, negation))
+    });
+    before || after
+}
+
+// Every phrase in the clause that no nearby negation cancels
+fn affirmed_phrases(clause: &[String]) -> impl Iterator<Item = &'static Phrase> + '_ {
+    POOL_PHRASES.iter().filter(move |phrase| {
+        (0..clause.len()).any(|start| {
+            phrase_at(clause, start, phrase.words) && !negated(clause, start, start + phrase.words.len())
+        })
+    })
+}
+
+// Weight of the strongest phrase in one sentence, 0.0 when nothing matched.
+// Phrases must sit inside one clause, so "a nice pool, table service" is not a pool table.
+fn sentence_weight(sentence: &str) -> f32 {
+    let sentence_words = words(sentence);
+    let swimming = SWIMMING_WORDS
+        .iter()
+        .any(|swim| contains_phrase(&sentence_words, &swim.split(' ').collect::<Vec<_>>()));
+
+    sentence
+        .split([',', ';', ':'])
+        .map(words)
+        .flat_map(|clause| affirmed_phrases(&clause).collect::<Vec<_>>())
+        .map(|phrase| if phrase.ambiguous && swimming { phrase.weight * SWIMMING_DISCOUNT } else { phrase.weight })
+        .fold(0.0, f32::max)
+}
+
+fn sentences(text: &str) -> impl Iterator<Item = &str> {
+    text.split(['.', '!', '?', '\n'])
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+}
+
+fn snippet(sentence: &str) -> String {
+    if sentence.chars().count() <= MAX_SNIPPET_CHARS {
+        sentence.to_string()
+    } else {
+        format!("{}...", sentence.chars().take(MAX_SNIPPET_CHARS).collect::<String>())
+    }
+}
+
+// Score reviews and summaries together. Every matching sentence is independent
+// evidence, combined noisy-OR style: 1 - product(1 - weight).
+pub fn score_texts<'a>(texts: impl IntoIterator<Item = &'a str>) -> TextEvidence {
+    let mut evidence = TextEvidence::default();
+    let mut miss_probability = 1.0;
+
+    for text in texts {
+        for sentence in sentences(text) {
+            let weight = sentence_weight(sentence);
+            // Discounted swimming-pool mentions are too weak to show as evidence
+            if weight >= 0.5 {
+                miss_probability *= 1.0 - weight;
+                evidence.snippets.push(snippet(sentence));
+            }
+        }
+    }
+
+    evidence.score = 1.0 - miss_probability;
+    evidence
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(text: &str) -> f32 {
+        score_texts([text]).score
+    }
+
+    #[test]
+    fn billiards_mentions_count() {
+        assert!(score("We played pool for hours.") >= 0.8);
+        assert!(score("Great pool table in the back room") >= 0.9);
+        assert!(score("Cheap beer and billiards") >= 0.9);
+        assert!(score("Perfect spot to shoot some stick") >= 0.8);
+        // Words that describe a pool hall as well as a swimming pool
+        assert!(score("Played pool up on the rooftop") >= 0.8);
+        assert!(score("A heated game of pool at the indoor bar") >= 0.8);
+    }
+
+    #[test]
+    fn swimming_and_table_service_do_not() {
+        assert_eq!(score("Nice pool, table service was quick"), 0.0);
+        assert_eq!(score("Got a table right away"), 0.0);
+        assert_eq!(score("Lounged by the pool all day"), 0.0);
+        // "played pool" about swimming is discounted below the evidence threshold
+        let swimming = score_texts(["The kids played pool games and swam until the lifeguard left"]);
+        assert_eq!(swimming.score, 0.0);
+        assert!(swimming.snippets.is_empty());
+    }
+
+    #[test]
+    fn negated_mentions_do_not_count() {
+        assert_eq!(score("There is no pool table anymore"), 0.0);
+        assert_eq!(score("They removed the pool table last year"), 0.0);
+        assert_eq!(score("The bar doesn't have billiards"), 0.0);
+        assert_eq!(score("Sadly the pool tables are gone"), 0.0);
+        assert_eq!(score("The pool table has been removed"), 0.0);
+        assert_eq!(score("We didn't play pool, the tables were taken"), 0.0);
+        // Negation only covers its own clause, and only the words next to the phrase
+        assert!(score("No cover charge, and two pool tables in the back") >= 0.9);
+        assert!(score("Two pool tables and no cover") >= 0.9);
+        assert!(score("No food to speak of but a pool table in the back") >= 0.9);
+        assert!(score("Not a pool table in sight but the snooker room is great") >= 0.8);
+    }
+
+    #[test]
+    fn matching_sentences_are_kept_as_snippets() {
+        let evidence = score_texts(["Great burgers. We played pool after dinner!", "Billiards on Tuesdays."]);
+        assert_eq!(evidence.snippets, vec!["We played pool after dinner", "Billiards on Tuesdays"]);
+        assert!(evidence.score > 0.95);
+    }
+}
@@ -0,0 +1,244 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use crate::models::Venue;
+
+// How the evidence for a venue is combined into `pool_table_probability`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FusionMethod {
+    // Each source is an independent chance of spotting a table: 1 - product(1 - weight * p).
+    // Evidence only ever adds up; a human rejection scales the result down.
+    NoisyOr,
+    // Naive Bayes in log-odds: the type prior plus weighted logit of each source.
+    // Weak or negative evidence (no table in any photo) pulls the score down.
+    LogOdds,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FusionSettings {
+    pub method: FusionMethod,
+    pub image_weight: f32,
+    pub text_weight: f32,
+    pub prior_weight: f32,
+    // What one human approval (or rejection) is worth, as a probability
+    pub human_weight: f32,
+    // Prior chance of a pool table by Google place type or `found_by` query,
+    // e.g. "bar" or "osm:sport=billiards". The highest matching prior is used.
+    pub type_priors: HashMap<String, f32>,
+    pub default_prior: f32,
+}
+
+impl Default for FusionSettings {
+    fn default() -> Self {
+        let type_priors = [
+            ("bar", 0.1),
+            ("pub", 0.12),
+            ("sports_bar", 0.2),
+            ("night_club", 0.05),
+            ("bowling_alley", 0.15),
+            ("osm:amenity=bar", 0.1),
+            ("osm:amenity=pub", 0.12),
+            // A mapper tagging sport=billiards is as good as a confirmed sighting
+            ("osm:sport=billiards", 0.95),
+            ("text:billiards", 0.2),
+            ("text:pool hall", 0.2),
+        ]
+        .iter()
+        .map(|(key, prior)| (key.to_string(), *prior))
+        .collect();
+
+        FusionSettings {
+            method: FusionMethod::NoisyOr,
+            image_weight: 1.0,
+            text_weight: 0.8,
+            prior_weight: 1.0,
+            human_weight: 0.9,
+            type_priors,
+            default_prior: 0.01,
+        }
+    }
+}
+
+// Keep logits finite; a classifier saying 0.0 is not certainty
+fn logit(p: f32) -> f32 {
+    let p = p.clamp(0.01, 0.99);
+    (p / (1.0 - p)).ln()
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+// Highest prior among the venue's place types and the queries that found it
+fn type_prior(venue: &Venue, settings: &FusionSettings) -> f32 {
+    venue.types
+        .iter()
+        .map(|t| t.as_str())
+        .chain(venue.found_by.iter().map(|q| q.strip_prefix("type:").unwrap_or(q)))
+        .filter_map(|key| settings.type_priors.get(key).copied())
+        .fold(settings.default_prior, f32::max)
+}
+
+// One source's chance of spotting a table. Weights above 1 would push the chance past
+// certainty and make the noisy-OR product negative, so it stays a probability.
+fn chance(weight: f32, p: f32) -> f32 {
+    (weight * p).clamp(0.0, 1.0)
+}
+
+fn fuse(venue: &Venue, settings: &FusionSettings) -> f32 {
+    let approvals = venue.human_approved.max(0);
+    let rejections = venue.human_rejected.max(0);
+
+    match settings.method {
+        FusionMethod::NoisyOr => {
+            let human_miss = 1.0 - chance(settings.human_weight, 1.0);
+            let mut miss = 1.0;
+            if let Some(image) = venue.image_score {
+                miss *= 1.0 - chance(settings.image_weight, image);
+            }
+            miss *= 1.0 - chance(settings.text_weight, venue.text_score);
+            miss *= 1.0 - chance(settings.prior_weight, venue.type_prior);
+            miss *= human_miss.powi(approvals);
+            (1.0 - miss) * human_miss.powi(rejections)
+        },
+        FusionMethod::LogOdds => {
+            let mut log_odds = settings.prior_weight * logit(venue.type_prior);
+            if let Some(image) = venue.image_score {
+                log_odds += settings.image_weight * logit(image);
+            }
+            // Most reviews never mention the games, so silence is not evidence against
+            if venue.text_score > 0.0 {
+                log_odds += settings.text_weight * logit(venue.text_score);
+            }
+            log_odds += logit(settings.human_weight) * (approvals - rejections) as f32;
+            sigmoid(log_odds)
+        },
+    }
+}
+
+// A prior this strong is a sighting in itself, like a mapper's sport=billiards tag
+const SIGHTING_PRIOR: f32 = 0.5;
+
+// Whether anything beyond a generic place-type prior points at a table: a photo the
+// classifier called a pool table, a review mention, a human approval or a sighting
+// prior. A sports bar's prior alone clears most probability thresholds.
+pub fn has_evidence(venue: &Venue) -> bool {
+    // Venues scored before fusion only kept their image probability
+    if venue.score_method.is_none() {
+        return venue.pool_table_probability > 0.0;
+    }
+    venue.image_score.map(|score| score >= 0.5).unwrap_or(false)
+        || venue.text_score > 0.0
+        || venue.human_approved > 0
+        || venue.type_prior >= SIGHTING_PRIOR
+}
+
+// Recompute a venue's components that depend on settings and its fused probability
+pub fn rescore_venue(venue: &mut Venue, settings: &FusionSettings) {
+    venue.type_prior = type_prior(venue, settings);
+    venue.pool_table_probability = fuse(venue, settings);
+    venue.score_method = Some(settings.method);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sports_bar() -> Venue {
+        let mut venue = Venue::new("Corner Sports Bar".to_string(), "place".to_string(), String::new(), 0.0, 0.0, 0.0);
+        venue.types = vec!["sports_bar".to_string()];
+        venue
+    }
+
+    #[test]
+    fn a_place_type_prior_alone_is_not_evidence() {
+        let mut venue = sports_bar();
+        rescore_venue(&mut venue, &FusionSettings::default());
+        assert!(venue.pool_table_probability >= 0.15);
+        assert!(!has_evidence(&venue));
+
+        venue.image_score = Some(0.1);
+        rescore_venue(&mut venue, &FusionSettings::default());
+        assert!(!has_evidence(&venue));
+    }
+
+    #[test]
+    fn photos_reviews_approvals_and_sightings_are_evidence() {
+        let settings = FusionSettings::default();
+        let mut photo = sports_bar();
+        photo.image_score = Some(0.9);
+        let mut review = sports_bar();
+        review.text_score = 0.8;
+        let mut approved = sports_bar();
+        approved.human_approved = 1;
+        let mut mapped = sports_bar();
+        mapped.found_by = vec!["osm:sport=billiards".to_string()];
+        for mut venue in [photo, review, approved, mapped] {
+            rescore_venue(&mut venue, &settings);
+            assert!(has_evidence(&venue), "{:?}", venue.name);
+        }
+    }
+
+    fn scored(image: Option<f32>, text: f32, prior: f32) -> Venue {
+        let mut venue = Venue::new("Venue".to_string(), "place".to_string(), String::new(), 0.0, 0.0, 0.0);
+        venue.image_score = image;
+        venue.text_score = text;
+        venue.type_prior = prior;
+        venue
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn noisy_or_combines_each_sources_miss_chance() {
+        let settings = FusionSettings::default();
+        // 1 - (1 - 0.8) * (1 - 0.8 * 0.5) * (1 - 0.1)
+        let mut venue = scored(Some(0.8), 0.5, 0.1);
+        assert_close(fuse(&venue, &settings), 0.892);
+
+        // No photos classified: only the text and the prior
+        assert_close(fuse(&scored(None, 0.5, 0.1), &settings), 1.0 - 0.6 * 0.9);
+
+        // An approval is one more 0.9 source, a rejection scales the result by 0.1
+        venue.human_approved = 1;
+        assert_close(fuse(&venue, &settings), 1.0 - 0.108 * 0.1);
+        venue.human_approved = 0;
+        venue.human_rejected = 1;
+        assert_close(fuse(&venue, &settings), 0.892 * 0.1);
+    }
+
+    #[test]
+    fn log_odds_adds_weighted_logits() {
+        let settings = FusionSettings { method: FusionMethod::LogOdds, ..FusionSettings::default() };
+        // Prior odds 1:9 times the photo's 4:1, the 50/50 review adds nothing: 4:9
+        let mut venue = scored(Some(0.8), 0.5, 0.1);
+        assert_close(fuse(&venue, &settings), 4.0 / 13.0);
+
+        // An approval multiplies the odds by 9 (human_weight 0.9): 36:9
+        venue.human_approved = 1;
+        assert_close(fuse(&venue, &settings), 0.8);
+
+        // A photo with no table in it is evidence against; logits are clamped at 1%
+        assert_close(fuse(&scored(Some(0.0), 0.0, 0.1), &settings), 1.0 / (1.0 + 9.0 * 99.0));
+    }
+
+    #[test]
+    fn weights_above_one_stay_probabilities() {
+        let settings = FusionSettings {
+            image_weight: 2.0,
+            text_weight: 3.0,
+            human_weight: 1.5,
+            ..FusionSettings::default()
+        };
+        // Two out-of-range factors would multiply back to a positive miss of 0.3
+        let mut venue = scored(Some(0.8), 0.5, 0.1);
+        assert_close(fuse(&venue, &settings), 1.0);
+        assert_close(fuse(&scored(Some(0.3), 0.0, 0.0), &settings), 0.6);
+
+        venue.human_rejected = 1;
+        assert_eq!(fuse(&venue, &settings), 0.0);
+    }
+}
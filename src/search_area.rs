@@ -0,0 +1,549 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::geometry::{haversine_distance, offset_point, SearchCircle, EARTH_RADIUS_METERS};
+
+// A ring of [longitude, latitude] points, in GeoJSON order. The last point may repeat the first.
+pub type Ring = Vec<[f64; 2]>;
+
+#[derive(Debug, Clone)]
+pub struct Polygon {
+    pub exterior: Ring,
+    pub holes: Vec<Ring>,
+}
+
+// A travel route and how far either side of it to search
+#[derive(Debug, Clone)]
+pub struct Route {
+    // [longitude, latitude] points, in GeoJSON order
+    pub points: Vec<[f64; 2]>,
+    pub corridor_meters: f64,
+}
+
+// The area a search should cover
+#[derive(Debug, Clone)]
+pub enum SearchArea {
+    Circle(SearchCircle),
+    // One or more polygons (a MultiPolygon, or a single Polygon/bbox)
+    Polygons(Vec<Polygon>),
+    Route(Route),
+}
+
+// How a search area is written in the config file or the web request.
+// Exactly one of the fields should be set.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct AreaSpec {
+    // Path to a GeoJSON file holding a Polygon/MultiPolygon geometry, Feature or FeatureCollection
+    pub geojson_file: Option<String>,
+    // The same GeoJSON, inline
+    pub geojson: Option<Value>,
+    // [min_longitude, min_latitude, max_longitude, max_latitude], the GeoJSON bbox order
+    pub bbox: Option<[f64; 4]>,
+    // A travel route with a corridor either side of it
+    pub route: Option<RouteSpec>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RouteSpec {
+    // Google encoded polyline, as returned by the Directions and Routes APIs
+    pub polyline: Option<String>,
+    // GeoJSON LineString, or a Feature wrapping one
+    pub geojson: Option<Value>,
+    // How far from the route a venue may be
+    pub corridor_meters: f64,
+}
+
+impl Default for RouteSpec {
+    fn default() -> Self {
+        RouteSpec {
+            polyline: None,
+            geojson: None,
+            corridor_meters: 2000.0,
+        }
+    }
+}
+
+impl AreaSpec {
+    pub fn resolve(&self) -> Result<SearchArea, Box<dyn std::error::Error>> {
+        let fields_set = [
+            self.geojson_file.is_some(),
+            self.geojson.is_some(),
+            self.bbox.is_some(),
+            self.route.is_some(),
+        ].iter().filter(|set| **set).count();
+        if fields_set != 1 {
+            return Err("Search area must set exactly one of geojson_file, geojson, bbox or route".into());
+        }
+
+        if let Some(path) = &self.geojson_file {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| format!("Could not read GeoJSON file {}: {}", path, e))?;
+            let geojson: Value = serde_json::from_str(&content)?;
+            Ok(SearchArea::Polygons(polygons_from_geojson(&geojson)?))
+        } else if let Some(geojson) = &self.geojson {
+            Ok(SearchArea::Polygons(polygons_from_geojson(geojson)?))
+        } else if let Some(bbox) = &self.bbox {
+            Ok(SearchArea::Polygons(vec![polygon_from_bbox(bbox)?]))
+        } else {
+            let route = self.route.as_ref().expect("checked above");
+            Ok(SearchArea::Route(route.resolve()?))
+        }
+    }
+}
+
+impl RouteSpec {
+    pub fn resolve(&self) -> Result<Route, Box<dyn std::error::Error>> {
+        let points = match (&self.polyline, &self.geojson) {
+            (Some(encoded), None) => decode_polyline(encoded)?,
+            (None, Some(geojson)) => line_from_geojson(geojson)?,
+            _ => return Err("Route needs exactly one of polyline or geojson".into()),
+        };
+        if points.len() < 2 {
+            return Err("Route needs at least two points".into());
+        }
+        if self.corridor_meters <= 0.0 {
+            return Err("Route corridor_meters must be positive".into());
+        }
+        Ok(Route { points, corridor_meters: self.corridor_meters })
+    }
+}
+
+// Decode a Google encoded polyline (precision 5) into [longitude, latitude] points
+pub fn decode_polyline(encoded: &str) -> Result<Vec<[f64; 2]>, Box<dyn std::error::Error>> {
+    let mut bytes = encoded.bytes();
+    let mut next_value = || -> Result<Option<i64>, String> {
+        let mut result: i64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = match bytes.next() {
+                Some(b) => b,
+                None if shift == 0 => return Ok(None),
+                None => return Err("Encoded polyline ends in the middle of a value".to_string()),
+            };
+            if !(63..=126).contains(&byte) || shift > 60 {
+                return Err(format!("Invalid character in encoded polyline: {:?}", byte as char));
+            }
+            let chunk = (byte - 63) as i64;
+            result |= (chunk & 0x1f) << shift;
+            shift += 5;
+            if chunk < 0x20 {
+                break;
+            }
+        }
+        Ok(Some(if result & 1 != 0 { !(result >> 1) } else { result >> 1 }))
+    };
+
+    let (mut lat, mut lon) = (0i64, 0i64);
+    let mut points = Vec::new();
+    while let Some(delta_lat) = next_value()? {
+        let delta_lon = next_value()?.ok_or("Encoded polyline has a latitude without a longitude")?;
+        lat += delta_lat;
+        lon += delta_lon;
+        points.push([lon as f64 / 1e5, lat as f64 / 1e5]);
+    }
+    Ok(points)
+}
+
+fn line_from_geojson(geojson: &Value) -> Result<Vec<[f64; 2]>, Box<dyn std::error::Error>> {
+    match geojson.get("type").and_then(Value::as_str) {
+        Some("LineString") => parse_positions(&geojson["coordinates"]),
+        Some("Feature") => line_from_geojson(&geojson["geometry"]),
+        other => Err(format!("Route GeoJSON must be a LineString, got {:?}", other).into()),
+    }
+}
+
+fn polygon_from_bbox(bbox: &[f64; 4]) -> Result<Polygon, Box<dyn std::error::Error>> {
+    let [min_lon, min_lat, max_lon, max_lat] = *bbox;
+    if min_lon >= max_lon || min_lat >= max_lat {
+        return Err(format!("Invalid bbox {:?}, expected [min_lon, min_lat, max_lon, max_lat]", bbox).into());
+    }
+    Ok(Polygon {
+        exterior: vec![
+            [min_lon, min_lat],
+            [max_lon, min_lat],
+            [max_lon, max_lat],
+            [min_lon, max_lat],
+            [min_lon, min_lat],
+        ],
+        holes: Vec::new(),
+    })
+}
+
+fn parse_positions(value: &Value) -> Result<Vec<[f64; 2]>, Box<dyn std::error::Error>> {
+    let points = value.as_array().ok_or("GeoJSON coordinates must be an array of positions")?;
+    let positions = points
+        .iter()
+        .map(|p| {
+            let lon = p.get(0).and_then(Value::as_f64);
+            let lat = p.get(1).and_then(Value::as_f64);
+            match (lon, lat) {
+                (Some(lon), Some(lat)) => Ok([lon, lat]),
+                _ => Err(format!("Invalid GeoJSON position: {}", p)),
+            }
+        })
+        .collect::<Result<_, String>>()?;
+    Ok(positions)
+}
+
+fn parse_ring(value: &Value) -> Result<Ring, Box<dyn std::error::Error>> {
+    let ring = parse_positions(value)?;
+    if ring.len() < 3 {
+        return Err("GeoJSON ring needs at least 3 positions".into());
+    }
+    Ok(ring)
+}
+
+fn parse_polygon(coordinates: &Value) -> Result<Polygon, Box<dyn std::error::Error>> {
+    let rings = coordinates.as_array().ok_or("Polygon coordinates must be an array of rings")?;
+    let (exterior, holes) = rings.split_first().ok_or("Polygon has no rings")?;
+    Ok(Polygon {
+        exterior: parse_ring(exterior)?,
+        holes: holes.iter().map(parse_ring).collect::<Result<_, _>>()?,
+    })
+}
+
+// Pull every Polygon/MultiPolygon out of a geometry, Feature or FeatureCollection
+pub fn polygons_from_geojson(geojson: &Value) -> Result<Vec<Polygon>, Box<dyn std::error::Error>> {
+    let geometry_type = geojson.get("type").and_then(Value::as_str).ok_or("GeoJSON object has no type")?;
+    let polygons = match geometry_type {
+        "Polygon" => vec![parse_polygon(&geojson["coordinates"])?],
+        "MultiPolygon" => geojson["coordinates"]
+            .as_array()
+            .ok_or("MultiPolygon coordinates must be an array of polygons")?
+            .iter()
+            .map(parse_polygon)
+            .collect::<Result<_, _>>()?,
+        "Feature" => polygons_from_geojson(&geojson["geometry"])?,
+        "FeatureCollection" => {
+            let mut polygons = Vec::new();
+            for feature in geojson["features"].as_array().ok_or("FeatureCollection has no features")? {
+                polygons.extend(polygons_from_geojson(feature)?);
+            }
+            polygons
+        },
+        other => return Err(format!("Unsupported GeoJSON type for a search area: {}", other).into()),
+    };
+    if polygons.is_empty() {
+        return Err("GeoJSON contains no polygons".into());
+    }
+    Ok(polygons)
+}
+
+// Even-odd ray casting in plain lon/lat, good enough at city scale
+fn ring_contains(ring: &Ring, lat: f64, lon: f64) -> bool {
+    let mut inside = false;
+    let mut j = ring.len() - 1;
+    for i in 0..ring.len() {
+        let [xi, yi] = ring[i];
+        let [xj, yj] = ring[j];
+        if (yi > lat) != (yj > lat) && lon < (xj - xi) * (lat - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+impl Polygon {
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        ring_contains(&self.exterior, lat, lon)
+            && !self.holes.iter().any(|hole| ring_contains(hole, lat, lon))
+    }
+
+    // Shortest distance in meters from a point to the polygon outline
+    fn distance_to_edge(&self, lat: f64, lon: f64) -> f64 {
+        std::iter::once(&self.exterior)
+            .chain(self.holes.iter())
+            .flat_map(|ring| ring.iter().zip(ring.iter().cycle().skip(1)))
+            .map(|(a, b)| distance_to_segment(lat, lon, *a, *b))
+            .fold(f64::INFINITY, f64::min)
+    }
+}
+
+// Distance from a point to a segment, projecting onto a flat plane around the point
+fn distance_to_segment(lat: f64, lon: f64, a: [f64; 2], b: [f64; 2]) -> f64 {
+    project_onto_segment(lat, lon, a, b).0
+}
+
+// (distance to the segment, how far along a->b the closest point sits from 0 to 1)
+fn project_onto_segment(lat: f64, lon: f64, a: [f64; 2], b: [f64; 2]) -> (f64, f64) {
+    let meters_per_deg_lat = EARTH_RADIUS_METERS.to_radians();
+    let meters_per_deg_lon = meters_per_deg_lat * lat.to_radians().cos();
+    let to_xy = |p: [f64; 2]| ((p[0] - lon) * meters_per_deg_lon, (p[1] - lat) * meters_per_deg_lat);
+
+    let (ax, ay) = to_xy(a);
+    let (bx, by) = to_xy(b);
+    let (dx, dy) = (bx - ax, by - ay);
+    let length_sq = dx * dx + dy * dy;
+    let t = if length_sq == 0.0 { 0.0 } else { (-(ax * dx + ay * dy) / length_sq).clamp(0.0, 1.0) };
+    let (px, py) = (ax + t * dx, ay + t * dy);
+    ((px * px + py * py).sqrt(), t)
+}
+
+// Direction of travel from a to b in radians, on a flat plane around a
+fn heading(a: [f64; 2], b: [f64; 2]) -> f64 {
+    ((b[1] - a[1]).to_radians()).atan2((b[0] - a[0]).to_radians() * a[1].to_radians().cos())
+}
+
+fn segment_length(a: [f64; 2], b: [f64; 2]) -> f64 {
+    haversine_distance(a[1], a[0], b[1], b[0])
+}
+
+impl Route {
+    // (meters along the route to the closest point, meters away from the route)
+    pub fn locate(&self, lat: f64, lon: f64) -> (f64, f64) {
+        let mut travelled = 0.0;
+        let mut best = (0.0, f64::INFINITY);
+        for segment in self.points.windows(2) {
+            let length = segment_length(segment[0], segment[1]);
+            let (offset, t) = project_onto_segment(lat, lon, segment[0], segment[1]);
+            if offset < best.1 {
+                best = (travelled + t * length, offset);
+            }
+            travelled += length;
+        }
+        best
+    }
+
+    pub fn distance_along(&self, lat: f64, lon: f64) -> f64 {
+        self.locate(lat, lon).0
+    }
+
+    // Circles strung along the route that cover the whole corridor.
+    // A circle of radius w * sqrt(2) covers a corridor of half-width w for 2w of route,
+    // so the circles are placed every 2w, plus one at every bend.
+    fn corridor_circles(&self) -> Vec<SearchCircle> {
+        let radius = self.corridor_meters * std::f64::consts::SQRT_2;
+        let spacing = self.corridor_meters * 2.0;
+
+        let mut circles = Vec::new();
+        let mut next_stop = 0.0;
+        let mut travelled = 0.0;
+        for segment in self.points.windows(2) {
+            let [a, b] = [segment[0], segment[1]];
+            let length = segment_length(a, b);
+            while next_stop <= travelled + length {
+                let t = if length == 0.0 { 0.0 } else { (next_stop - travelled) / length };
+                let lon = a[0] + t * (b[0] - a[0]);
+                let lat = a[1] + t * (b[1] - a[1]);
+                circles.push(SearchCircle::new(lat, lon, radius));
+                next_stop += spacing;
+            }
+            travelled += length;
+        }
+
+        // Make sure the destination itself is covered
+        let [lon, lat] = self.points[self.points.len() - 1];
+        if next_stop - spacing < travelled {
+            circles.push(SearchCircle::new(lat, lon, radius));
+        }
+
+        // The spacing only holds along a straight line. On the outside of a bend the
+        // corridor reaches up to w from the vertex, so every turn gets its own circle.
+        for corner in self.points.windows(3) {
+            let turn = (heading(corner[1], corner[2]) - heading(corner[0], corner[1])).rem_euclid(std::f64::consts::TAU);
+            if turn > 1e-6 && turn < std::f64::consts::TAU - 1e-6 {
+                circles.push(SearchCircle::new(corner[1][1], corner[1][0], radius));
+            }
+        }
+        circles
+    }
+}
+
+impl SearchArea {
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        match self {
+            SearchArea::Circle(circle) => circle.contains(lat, lon),
+            SearchArea::Polygons(polygons) => polygons.iter().any(|p| p.contains(lat, lon)),
+            SearchArea::Route(route) => route.locate(lat, lon).1 <= route.corridor_meters,
+        }
+    }
+
+    // Circles that together cover the area, for providers that only search circles.
+    // Polygons are laid over with a square grid of `cell_radius_meters` circles and the
+    // cells that do not touch any polygon are dropped.
+    pub fn discovery_circles(&self, cell_radius_meters: f64) -> Vec<SearchCircle> {
+        let polygons = match self {
+            SearchArea::Circle(circle) => return vec![*circle],
+            SearchArea::Route(route) => return route.corridor_circles(),
+            SearchArea::Polygons(polygons) => polygons,
+        };
+
+        let points = polygons.iter().flat_map(|p| p.exterior.iter());
+        let (mut min_lon, mut min_lat, mut max_lon, mut max_lat) = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+        for [lon, lat] in points {
+            min_lon = min_lon.min(*lon);
+            min_lat = min_lat.min(*lat);
+            max_lon = max_lon.max(*lon);
+            max_lat = max_lat.max(*lat);
+        }
+
+        // A circle of radius r covers a square of side r * sqrt(2). The grid is a little
+        // tighter than that, so rounding and the flat-earth offsets never leave the
+        // corner of a cell just outside its circle.
+        let step = cell_radius_meters * std::f64::consts::SQRT_2 * 0.95;
+        let height = haversine_distance(min_lat, min_lon, max_lat, min_lon);
+        let rows = (height / step).ceil().max(1.0) as usize;
+
+        let mut circles = Vec::new();
+        for row in 0..rows {
+            let (lat, _) = offset_point(min_lat, min_lon, step * (row as f64 + 0.5), 0.0);
+            let width = haversine_distance(lat, min_lon, lat, max_lon);
+            let cols = (width / step).ceil().max(1.0) as usize;
+            for col in 0..cols {
+                let (_, lon) = offset_point(lat, min_lon, 0.0, step * (col as f64 + 0.5));
+                let touches = polygons.iter().any(|p| {
+                    p.contains(lat, lon) || p.distance_to_edge(lat, lon) <= cell_radius_meters
+                });
+                if touches {
+                    circles.push(SearchCircle::new(lat, lon, cell_radius_meters));
+                }
+            }
+        }
+        circles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn square(min_lon: f64, min_lat: f64, size: f64) -> Value {
+        json!([[min_lon, min_lat], [min_lon + size, min_lat], [min_lon + size, min_lat + size], [min_lon, min_lat + size], [min_lon, min_lat]])
+    }
+
+    #[test]
+    fn polygon_contains_points_outside_its_hole() {
+        let polygons = polygons_from_geojson(&json!({
+            "type": "Polygon",
+            "coordinates": [square(0.0, 0.0, 10.0), square(4.0, 4.0, 2.0)],
+        })).unwrap();
+        assert_eq!(polygons.len(), 1);
+        let polygon = &polygons[0];
+        assert!(polygon.contains(2.0, 2.0));
+        assert!(polygon.contains(5.0, 8.0));
+        // In the hole, then outside altogether
+        assert!(!polygon.contains(5.0, 5.0));
+        assert!(!polygon.contains(5.0, 11.0));
+        assert!(!polygon.contains(-0.5, 5.0));
+    }
+
+    #[test]
+    fn multipolygons_and_feature_collections_cover_each_part() {
+        let multi = json!({
+            "type": "MultiPolygon",
+            "coordinates": [[square(0.0, 0.0, 1.0)], [square(5.0, 5.0, 1.0)]],
+        });
+        let collection = json!({
+            "type": "FeatureCollection",
+            "features": [{ "type": "Feature", "properties": {}, "geometry": multi.clone() }],
+        });
+        for geojson in [multi, collection] {
+            let area = SearchArea::Polygons(polygons_from_geojson(&geojson).unwrap());
+            assert!(area.contains(0.5, 0.5));
+            assert!(area.contains(5.5, 5.5));
+            assert!(!area.contains(3.0, 3.0));
+        }
+        assert!(polygons_from_geojson(&json!({ "type": "Point", "coordinates": [0.0, 0.0] })).is_err());
+        assert!(polygons_from_geojson(&json!({ "type": "Polygon", "coordinates": [[[0.0, 0.0], [1.0, 1.0]]] })).is_err());
+    }
+
+    #[test]
+    fn bbox_must_not_be_inverted() {
+        let spec = |bbox: [f64; 4]| AreaSpec { bbox: Some(bbox), ..AreaSpec::default() };
+        assert!(spec([-75.0, 40.0, -74.9, 40.1]).resolve().is_ok());
+        // Longitudes swapped, latitudes swapped, and an empty box
+        assert!(spec([-74.9, 40.0, -75.0, 40.1]).resolve().is_err());
+        assert!(spec([-75.0, 40.1, -74.9, 40.0]).resolve().is_err());
+        assert!(spec([-75.0, 40.0, -75.0, 40.1]).resolve().is_err());
+        // And exactly one kind of area
+        assert!(AreaSpec::default().resolve().is_err());
+    }
+
+    #[test]
+    fn discovery_circles_cover_the_polygon() {
+        // An L-shaped area about 8 x 11 km
+        let polygon = polygons_from_geojson(&json!({
+            "type": "Polygon",
+            "coordinates": [[[-75.0, 40.0], [-74.9, 40.0], [-74.9, 40.03], [-74.96, 40.03], [-74.96, 40.1], [-75.0, 40.1], [-75.0, 40.0]]],
+        })).unwrap();
+        let area = SearchArea::Polygons(polygon.clone());
+        let circles = area.discovery_circles(1000.0);
+        let covered = |lat: f64, lon: f64| circles.iter().any(|c| c.contains(lat, lon));
+
+        for [lon, lat] in &polygon[0].exterior {
+            assert!(covered(*lat, *lon), "vertex ({}, {}) uncovered", lat, lon);
+        }
+        let mut samples = 0;
+        for i in 0..=40 {
+            for j in 0..=40 {
+                let (lat, lon) = (40.0 + 0.1 * i as f64 / 40.0, -75.0 + 0.1 * j as f64 / 40.0);
+                if area.contains(lat, lon) {
+                    samples += 1;
+                    assert!(covered(lat, lon), "({}, {}) uncovered", lat, lon);
+                }
+            }
+        }
+        assert!(samples > 500);
+        // No circle is laid over the empty corner of the L
+        assert!(!covered(40.08, -74.91));
+    }
+
+    fn route(points: Vec<[f64; 2]>, corridor_meters: f64) -> Route {
+        Route { points, corridor_meters }
+    }
+
+    #[test]
+    fn decodes_googles_reference_polyline() {
+        let points = decode_polyline("_p~iF~ps|U_ulLnnqC_mqNvxq`@").unwrap();
+        let expected = [[-120.2, 38.5], [-120.95, 40.7], [-126.453, 43.252]];
+        assert_eq!(points.len(), expected.len());
+        for (point, want) in points.iter().zip(expected.iter()) {
+            assert!((point[0] - want[0]).abs() < 1e-9 && (point[1] - want[1]).abs() < 1e-9, "{:?} != {:?}", point, want);
+        }
+        assert!(decode_polyline("_p~iF~ps|U_ulL").is_err());
+        assert!(decode_polyline("_p~iF ").is_err());
+    }
+
+    #[test]
+    fn venues_are_ordered_along_the_route() {
+        // East for about 8.5 km, then north
+        let route = route(vec![[-75.0, 40.0], [-74.9, 40.0], [-74.9, 40.1]], 500.0);
+        let (along, away) = route.locate(40.0, -75.0);
+        assert!(along.abs() < 1.0 && away < 1.0);
+
+        // Passed in the order: just off the start, mid first leg, after the turn
+        let stops = [(40.001, -74.99), (39.999, -74.95), (40.05, -74.901)];
+        let distances: Vec<f64> = stops.iter().map(|(lat, lon)| route.distance_along(*lat, *lon)).collect();
+        assert!(distances.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", distances);
+        let first_leg = haversine_distance(40.0, -75.0, 40.0, -74.9);
+        assert!(distances[2] > first_leg);
+        assert!((route.locate(40.05, -74.901).1 - haversine_distance(40.05, -74.901, 40.05, -74.9)).abs() < 1.0);
+
+        let area = SearchArea::Route(route);
+        assert!(area.contains(40.003, -74.95));
+        assert!(!area.contains(40.01, -74.95));
+    }
+
+    #[test]
+    fn corridor_circles_cover_the_outside_of_a_bend() {
+        let corridor = 500.0;
+        let route = route(vec![[-75.0, 40.0], [-74.95, 40.0], [-74.95, 40.05]], corridor);
+        let circles = route.corridor_circles();
+        // Sample the corridor, including just inside its edge around the corner
+        let mut samples = 0;
+        for i in -20..=120 {
+            for j in -20..=120 {
+                let (lat, lon) = (40.0 + 0.0005 * i as f64, -75.0 + 0.0005 * j as f64);
+                let (_, away) = route.locate(lat, lon);
+                if away <= corridor * 0.99 {
+                    samples += 1;
+                    assert!(circles.iter().any(|c| c.contains(lat, lon)), "({}, {}) uncovered", lat, lon);
+                }
+            }
+        }
+        assert!(samples > 1000);
+    }
+}
@@ -0,0 +1,246 @@
+use image::ImageFormat;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use crate::classifier::{Classifier, ImageResult, Inference};
+
+// A pool table at the back of a 4032px room shot is a few pixels once the whole
+// photo is shrunk to the model's input size. Tiled mode cuts each photo into
+// overlapping tiles, classifies every tile with the configured backend, and keeps
+// the best-scoring tile (or the whole photo) as the photo's result.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TilingSettings {
+    pub enabled: bool,
+    // Tile edge in pixels of the original photo; smaller photos are classified whole
+    pub tile_size: u32,
+    // Fraction of a tile shared with its neighbour, from 0.0 up to 0.9
+    pub overlap: f32,
+    // Also classify the whole photo, for tables that fill the frame
+    pub full_image: bool,
+}
+
+impl Default for TilingSettings {
+    fn default() -> Self {
+        TilingSettings {
+            enabled: false,
+            tile_size: 640,
+            overlap: 0.25,
+            full_image: true,
+        }
+    }
+}
+
+// Where in the photo a result came from, in pixels of the original photo
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TileRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Tiles are written to a scratch folder per call, removed once classified
+static TILE_FOLDERS: AtomicUsize = AtomicUsize::new(0);
+
+// Tile offsets along one side: evenly stepped, with the last tile flush with the edge
+fn tile_starts(length: u32, tile: u32, step: u32) -> Vec<u32> {
+    if length <= tile {
+        return vec![0];
+    }
+    let mut starts: Vec<u32> = (0..).map(|i| i * step).take_while(|start| start + tile < length).collect();
+    starts.push(length - tile);
+    starts
+}
+
+// Tiles covering a photo, none when the photo already fits in one tile
+pub fn tile_regions(width: u32, height: u32, settings: &TilingSettings) -> Vec<TileRegion> {
+    let tile = settings.tile_size.max(1);
+    if width <= tile && height <= tile {
+        return Vec::new();
+    }
+    let step = ((tile as f32 * (1.0 - settings.overlap.clamp(0.0, 0.9))) as u32).max(1);
+    let mut regions = Vec::new();
+    for y in tile_starts(height, tile, step) {
+        for x in tile_starts(width, tile, step) {
+            regions.push(TileRegion { x, y, width: tile.min(width), height: tile.min(height) });
+        }
+    }
+    regions
+}
+
+pub struct TiledClassifier {
+    inner: Arc<dyn Classifier>,
+    settings: TilingSettings,
+}
+
+impl TiledClassifier {
+    pub fn new(inner: Arc<dyn Classifier>, settings: TilingSettings) -> Self {
+        TiledClassifier { inner, settings }
+    }
+
+    fn classify_tiles(&self, image_paths: &[PathBuf], tile_folder: &Path) -> Result<Inference, Box<dyn std::error::Error>> {
+        // Every pass sent to the backend, with the photo and tile it belongs to
+        let mut passes: Vec<(PathBuf, String, Option<TileRegion>)> = Vec::new();
+        for path in image_paths {
+            let file = path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+            let image = image::open(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+            let regions = tile_regions(image.width(), image.height(), &self.settings);
+            if self.settings.full_image || regions.is_empty() {
+                passes.push((path.clone(), file.clone(), None));
+            }
+
+            let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+            for (index, region) in regions.into_iter().enumerate() {
+                let tile_path = tile_folder.join(format!("{}__tile{}.jpg", stem, index));
+                image
+                    .crop_imm(region.x, region.y, region.width, region.height)
+                    .to_rgb8()
+                    .save_with_format(&tile_path, ImageFormat::Jpeg)?;
+                passes.push((tile_path, file.clone(), Some(region)));
+            }
+        }
+
+        // Tiles and photos alike are kept by the backend; negatives are handled below
+        let pass_paths: Vec<PathBuf> = passes.iter().map(|(path, _, _)| path.clone()).collect();
+        let raw = self.inner.classify(&pass_paths, true)?;
+
+        // A photo's result is its best-scoring pass, the whole photo winning ties
+        let mut best: HashMap<String, ImageResult> = HashMap::new();
+        for (path, photo, region) in passes {
+            let pass_file = path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+            let Some(result) = raw.images.get(&pass_file) else { continue };
+            let candidate = ImageResult { file: photo.clone(), tile: region, ..result.clone() };
+            let better = best
+                .get(&photo)
+                .map(|current| candidate.pool_table_score() > current.pool_table_score())
+                .unwrap_or(true);
+            if better {
+                best.insert(photo, candidate);
+            }
+        }
+
+        let mut inference = Inference::default();
+        for (_, result) in best {
+            inference.record(result);
+        }
+        Ok(inference)
+    }
+}
+
+impl Classifier for TiledClassifier {
+    fn name(&self) -> String {
+        format!("{} on {}px tiles", self.inner.name(), self.settings.tile_size)
+    }
+
+    fn classify(&self, image_paths: &[PathBuf], save_negative: bool) -> Result<Inference, Box<dyn std::error::Error>> {
+        let tile_folder = std::env::temp_dir().join(format!("racknroll_tiles_{}_{}",
+            std::process::id(), TILE_FOLDERS.fetch_add(1, Ordering::Relaxed)));
+        std::fs::create_dir_all(&tile_folder)?;
+        let inference = self.classify_tiles(image_paths, &tile_folder);
+        if let Err(e) = std::fs::remove_dir_all(&tile_folder) {
+            eprintln!("Error removing tile folder {}: {}", tile_folder.display(), e);
+        }
+        let inference = inference?;
+
+        // Like the backends, drop photos without a table unless they are kept
+        if !save_negative {
+            for path in image_paths {
+                let file = path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+                if inference.images.get(&file).map(|r| r.pool_table_score() < 0.5).unwrap_or(false) {
+                    if let Err(e) = std::fs::remove_file(path) {
+                        if e.kind() != std::io::ErrorKind::NotFound {
+                            return Err(e.into());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(inference)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::MockClassifier;
+
+    fn settings(tile_size: u32, overlap: f32) -> TilingSettings {
+        TilingSettings { enabled: true, tile_size, overlap, full_image: true }
+    }
+
+    #[test]
+    fn last_tile_sits_flush_with_the_edge() {
+        // 640px tiles stepping 480px: the third tile is pulled back to end at 1500
+        assert_eq!(tile_starts(1500, 640, 480), vec![0, 480, 860]);
+        // An exact fit does not add a duplicate tile
+        assert_eq!(tile_starts(1280, 640, 640), vec![0, 640]);
+        assert_eq!(tile_starts(640, 640, 480), vec![0]);
+    }
+
+    #[test]
+    fn tiles_cover_the_photo() {
+        let regions = tile_regions(1500, 700, &settings(640, 0.25));
+        assert_eq!(regions.len(), 6);
+        assert_eq!(regions[0], TileRegion { x: 0, y: 0, width: 640, height: 640 });
+        assert_eq!(regions[5], TileRegion { x: 860, y: 60, width: 640, height: 640 });
+        assert!(regions.iter().all(|r| r.x + r.width <= 1500 && r.y + r.height <= 700));
+    }
+
+    #[test]
+    fn overlap_is_clamped() {
+        // Overlap above 0.9 steps 64px at most, not zero (which would never end)
+        let dense = tile_regions(1000, 640, &settings(640, 5.0));
+        assert_eq!(dense.iter().map(|r| r.x).collect::<Vec<_>>(), vec![0, 64, 128, 192, 256, 320, 360]);
+        // Negative overlap is none at all, not gaps between tiles
+        let sparse = tile_regions(1500, 640, &settings(640, -1.0));
+        assert_eq!(sparse.iter().map(|r| r.x).collect::<Vec<_>>(), vec![0, 640, 860]);
+    }
+
+    #[test]
+    fn photos_within_one_tile_are_not_tiled() {
+        assert!(tile_regions(640, 480, &settings(640, 0.25)).is_empty());
+        assert!(tile_regions(100, 100, &settings(640, 0.25)).is_empty());
+        // Only one side too long: tiles span the short side whole
+        let regions = tile_regions(1000, 300, &settings(640, 0.25));
+        assert_eq!(regions, vec![
+            TileRegion { x: 0, y: 0, width: 640, height: 300 },
+            TileRegion { x: 360, y: 0, width: 640, height: 300 },
+        ]);
+    }
+
+    #[test]
+    fn records_the_winning_tile() {
+        let folder = std::env::temp_dir().join(format!("racknroll_tiled_test_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let table = folder.join("a_0.jpg");
+        let empty = folder.join("b_0.jpg");
+        image::RgbImage::new(1500, 700).save(&table).unwrap();
+        image::RgbImage::new(1500, 700).save(&empty).unwrap();
+
+        // Only the fourth tile of a_0 (x 0, y 60) shows a table
+        let fixture = folder.join("fixture.json");
+        std::fs::write(&fixture, serde_json::json!({
+            "images": { "a_0__tile3": { "class_name": "pool_table", "confidence": 0.8 } },
+            "default": { "class_name": "no_pool_table", "confidence": 0.9 },
+        }).to_string()).unwrap();
+        let inner: Arc<dyn Classifier> = Arc::new(MockClassifier::load(&fixture).unwrap());
+        let classifier = TiledClassifier::new(inner, settings(640, 0.25));
+
+        let inference = classifier.classify(&[table.clone(), empty.clone()], false).unwrap();
+        let best = &inference.images["a_0.jpg"];
+        assert_eq!(best.class_name, "pool_table");
+        assert_eq!(best.tile, Some(TileRegion { x: 0, y: 60, width: 640, height: 640 }));
+        assert!((inference.probability - 0.8).abs() < 1e-6);
+        // Without a table anywhere the whole photo's result stands, and the photo is dropped
+        let none = &inference.images["b_0.jpg"];
+        assert_eq!(none.class_name, "no_pool_table");
+        assert_eq!(none.tile, None);
+        assert!(table.exists());
+        assert!(!empty.exists());
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -1,15 +1,12 @@
 use actix_web::{web, App, HttpResponse, HttpServer, Result};
 use actix_files::Files;
-use actix_cors;
 use serde::{Deserialize, Serialize};
 use std::path::Path;
-use std::fs;
 use actix_web::web::Bytes;
 use futures::StreamExt;
 use tokio::sync::mpsc;
 use std::sync::Mutex;
 use lazy_static::lazy_static;
-use urlencoding;
 use futures::future::BoxFuture;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -25,26 +22,215 @@ lazy_static! {
 }
 
 // Import only what we need
-use crate::{Config, Location, Processing, search_pool_tables};
+use crate::{Config, Coverage, EarlyStopSettings, GeocoderBackend, GeocodingSettings, Location, Processing, Providers, search_pool_tables};
+use crate::cost::{CostTracker, PriceTable};
+use crate::geocoding::GeocodedLocation;
+use crate::google_api::RetryPolicy;
+use crate::google_places_photos_reviews::{AuthorAttribution, DownloadSettings};
+use crate::python_worker::PythonWorkerSettings;
+use crate::classifier::ClassifierSettings;
+use crate::photo_manifest::{venue_dir_name, venue_photos, PhotoManifest, CONFIRMED_DIR, NEGATIVE_DIR};
+use crate::google_places_search::{default_place_fields, OpeningHours};
+use crate::score_fusion::{has_evidence, rescore_venue, FusionMethod, FusionSettings};
+use crate::planning::{plan_search, PlanningSettings};
+use crate::search_area::{AreaSpec, RouteSpec, SearchArea};
+use crate::storage::{content_type_for, PhotoStorage};
+use crate::tiled_classifier::TileRegion;
 
 #[derive(Deserialize, Debug)]
 pub struct SearchParams {
-    latitude: f64,
-    longitude: f64,
-    radius: f64,
+    // Either a center (coordinates or an address) and radius, or an `area` (inline GeoJSON or bbox)
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    radius: Option<f64>,
+    #[serde(default)]
+    address: Option<String>,
+    #[serde(default)]
+    geocoder: Option<GeocoderBackend>,
+    #[serde(default)]
+    area: Option<AreaSpec>,
+    #[serde(flatten)]
+    options: SearchOptions,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RouteSearchParams {
+    route: RouteSpec,
+    #[serde(flatten)]
+    options: SearchOptions,
+}
+
+// Settings shared by every kind of search request
+#[derive(Deserialize, Debug)]
+pub struct SearchOptions {
     months_threshold: i64,
     save_negative: bool,
     reprocess_all: bool,
+    #[serde(default)]
+    tiled: bool,
+    #[serde(default)]
+    keywords: Vec<String>,
+    #[serde(default)]
+    use_osm: bool,
+    #[serde(default)]
+    skip_closed: bool,
+    // Stop the search once the estimated API cost reaches this many USD
+    #[serde(default)]
+    budget: Option<f64>,
+    // Only discover venues and return a plan of what a real search would process
+    #[serde(default)]
+    dry_run: bool,
+    // Classify photos one at a time and stop once a venue is clearly positive
+    #[serde(default)]
+    early_stop: bool,
+}
+
+// The config file sections a web search shares with command-line runs. What to search
+// for and how to treat known venues come from the request instead.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SharedSettings {
+    pub coverage: Coverage,
+    pub providers: Providers,
+    pub geocoding: GeocodingSettings,
+    pub retry: RetryPolicy,
+    pub prices: PriceTable,
+    pub place_fields: Vec<String>,
+    pub fusion: FusionSettings,
+    pub downloads: DownloadSettings,
+    pub planning: PlanningSettings,
+    pub early_stop: EarlyStopSettings,
+    pub python_worker: PythonWorkerSettings,
+    pub classifier: ClassifierSettings,
+}
+
+impl Default for SharedSettings {
+    fn default() -> Self {
+        SharedSettings {
+            coverage: Coverage::default(),
+            providers: Providers::default(),
+            geocoding: GeocodingSettings::default(),
+            retry: RetryPolicy::default(),
+            prices: PriceTable::default(),
+            place_fields: default_place_fields(),
+            fusion: FusionSettings::default(),
+            downloads: DownloadSettings::default(),
+            planning: PlanningSettings::default(),
+            early_stop: EarlyStopSettings::default(),
+            python_worker: PythonWorkerSettings::default(),
+            classifier: ClassifierSettings::default(),
+        }
+    }
+}
+
+impl SharedSettings {
+    // Read from the command line's config file; defaults when there is none
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                println!("No config file at {}, using default settings", path);
+                return Ok(SharedSettings::default());
+            },
+            Err(e) => return Err(format!("Could not read {}: {}", path, e).into()),
+        };
+        Ok(serde_yaml::from_str(&content).map_err(|e| format!("Invalid settings in {}: {}", path, e))?)
+    }
 }
 
+impl SearchOptions {
+    fn to_config(&self, location: Option<Location>, area: Option<AreaSpec>, settings: &SharedSettings) -> Config {
+        Config {
+            location,
+            area,
+            processing: Processing {
+                months_threshold: self.months_threshold,
+                reprocess_all: self.reprocess_all,
+                save_negative_images: self.save_negative,
+                skip_permanently_closed: self.skip_closed,
+            },
+            place_types: vec!["bar".to_string(), "restaurant".to_string(), "hotel".to_string()],
+            coverage: Coverage {
+                tiled: self.tiled,
+                ..settings.coverage.clone()
+            },
+            keywords: self.keywords.clone(),
+            providers: {
+                let mut providers = settings.providers.clone();
+                providers.overpass.enabled = self.use_osm;
+                providers
+            },
+            geocoding: settings.geocoding.clone(),
+            retry: settings.retry.clone(),
+            prices: settings.prices.clone(),
+            budget: self.budget,
+            place_fields: settings.place_fields.clone(),
+            downloads: settings.downloads.clone(),
+            fusion: settings.fusion.clone(),
+            planning: settings.planning.clone(),
+            early_stop: EarlyStopSettings {
+                enabled: self.early_stop,
+                ..settings.early_stop.clone()
+            },
+            python_worker: settings.python_worker.clone(),
+            classifier: settings.classifier.clone(),
+        }
+    }
+}
+
+// Venues below this are not worth showing, and neither are venues carried by their
+// place type alone (see `has_evidence`)
+const MIN_RESULT_PROBABILITY: f32 = 0.15;
+
 #[derive(Serialize)]
 pub struct VenueResponse {
     name: String,
     address: String,
     probability: f32,
     human_approved: i64,
-    photos: Vec<String>,
+    human_rejected: i64,
+    // The evidence behind `probability`
+    image_score: Option<f32>,
+    type_prior: f32,
+    score_method: Option<FusionMethod>,
+    photos: Vec<VenuePhoto>,
+    photos_skipped: usize,
     place_id: String,
+    found_by: Vec<String>,
+    types: Vec<String>,
+    business_status: Option<String>,
+    website: Option<String>,
+    phone: Option<String>,
+    rating: Option<f64>,
+    price_level: Option<String>,
+    opening_hours: Option<OpeningHours>,
+    text_score: f32,
+    text_snippets: Vec<String>,
+    // Only set for route searches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    route_distance_meters: Option<f64>,
+}
+
+// An evidence photo, what the classifier made of it, and the credit Google requires alongside it
+#[derive(Serialize)]
+pub struct VenuePhoto {
+    url: String,
+    width_px: Option<u32>,
+    height_px: Option<u32>,
+    author_attributions: Vec<AuthorAttribution>,
+    // Chance of a pool table, None for photos not classified yet
+    score: Option<f32>,
+    class_name: Option<String>,
+    confidence: Option<f32>,
+    // Where in the photo a tiled run found the table
+    tile: Option<TileRegion>,
+}
+
+#[derive(Serialize)]
+pub struct SearchResponse {
+    // Set when the search was given an address instead of coordinates
+    resolved_location: Option<GeocodedLocation>,
+    venues: Vec<VenueResponse>,
 }
 
 #[derive(Clone)]
@@ -53,6 +239,9 @@ pub struct AppState {
     pub cred_path: String,
     pub output_dir: String,
     pub model_path: String,
+    pub storage: Arc<dyn PhotoStorage>,
+    // The config file's settings, for searches and feedback alike
+    pub settings: SharedSettings,
 }
 
 // Add timestamp to connection info
@@ -123,140 +312,194 @@ pub async fn search_venues(
     // println!("  Save negative: {}", params.save_negative);
     // println!("  Reprocess all: {}", params.reprocess_all);
 
-    let config = Config {
-        location: Location {
+    // Reading GeoJSON files off the server's disk is only allowed from the CLI config
+    if params.area.as_ref().is_some_and(|area| area.geojson_file.is_some()) {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "error": "geojson_file is not accepted over the web API, send the GeoJSON inline"
+        })));
+    }
+
+    let has_center = (params.latitude.is_some() && params.longitude.is_some()) || params.address.is_some();
+    let location = match params.radius {
+        Some(radius_meters) if has_center => Some(Location {
             latitude: params.latitude,
             longitude: params.longitude,
-            radius_meters: params.radius,
-        },
-        processing: Processing {
-            months_threshold: params.months_threshold,
-            reprocess_all: params.reprocess_all,
-            save_negative_images: params.save_negative,
-        },
-        place_types: vec!["bar".to_string(), "restaurant".to_string(), "hotel".to_string()],
+            radius_meters,
+            address: params.address.clone(),
+        }),
+        _ => None,
+    };
+
+    let mut config = params.options.to_config(location, params.area.clone(), &data.settings);
+    if let Some(backend) = params.geocoder {
+        config.geocoding.backend = backend;
+    }
+    run_search(config, params.options.dry_run, &data).await
+}
+
+// Search along a travel route; venues come back in the order you pass them
+pub async fn search_route(
+    params: web::Json<RouteSearchParams>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let area = AreaSpec {
+        route: Some(params.route.clone()),
+        ..AreaSpec::default()
     };
+    let config = params.options.to_config(None, Some(area), &data.settings);
+    run_search(config, params.options.dry_run, &data).await
+}
 
+// Status callback that forwards messages to every open /api/status stream
+fn broadcast_status() -> impl Fn(&str) -> BoxFuture<'static, ()> + Send + Sync + 'static {
     // Get all active connections
     let connections = ACTIVE_CONNECTIONS.lock().unwrap().clone();
 
+    move |msg: &str| -> BoxFuture<'static, ()> {
+        let connections = connections.clone();
+        let msg = msg.to_string();
+        println!("Sending status update: {}", msg);
+        
+        Box::pin(async move {
+            for conn in &connections {
+                if let Err(e) = conn.sender.send(msg.clone()).await {
+                    println!("Error sending status: {}", e);
+                }
+            }
+        })
+    }
+}
+
+async fn run_search(mut config: Config, dry_run: bool, data: &AppState) -> Result<HttpResponse> {
+    // Every billable call in this search is counted here
+    let costs = Arc::new(CostTracker::new(config.prices.clone(), config.budget));
+    let resolved_location = match config.resolve_location(&data.api_key, &costs).await {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": format!("Could not resolve location: {}", e)
+            })));
+        }
+    };
+
+    let search_area = match config.search_area() {
+        Ok(area) => area,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "error": format!("Invalid search area: {}", e)
+            })));
+        }
+    };
+
+    if dry_run {
+        return match plan_search(config, &data.api_key, &costs, Some(broadcast_status())).await {
+            Ok(plan) => Ok(HttpResponse::Ok().json(json!({
+                "resolved_location": resolved_location,
+                "plan": plan
+            }))),
+            Err(e) => Ok(HttpResponse::InternalServerError().json(json!({"error": e.to_string()})))
+        };
+    }
+
     let result = search_pool_tables(
         config.clone(), // Clone config since we'll need it later
         &data.api_key,
         &data.cred_path,
         &data.output_dir,
+        data.storage.clone(),
         &data.model_path,
-        Some(move |msg: &str| -> BoxFuture<'static, ()> {
-            let connections = connections.clone();
-            let msg = msg.to_string();
-            println!("Sending status update: {}", msg);
-            
-            Box::pin(async move {
-                for conn in &connections {
-                    if let Err(e) = conn.sender.send(msg.clone()).await {
-                        println!("Error sending status: {}", e);
-                    }
-                }
-            })
-        })
+        costs,
+        Some(broadcast_status())
     ).await;
 
     match result {
         Ok(venues) => {
             println!("Search complete, found {} total venues", venues.len());
-            let venues_response: Vec<VenueResponse> = venues
-                .into_iter()
-                .filter(|v| {
-                    let has_pool = v.pool_table_probability > 0.0;
-                    let in_radius = calculate_distance(
-                        params.latitude,
-                        params.longitude,
-                        v.latitude,
-                        v.longitude
-                    ) <= params.radius;
-                    
-                    println!("Venue '{}' coordinates: ({}, {})", 
-                        v.name, v.latitude, v.longitude);
-                    
-                    has_pool && in_radius
-                })
-                .map(|v| {
-                    let name = v.name.clone();
-                    let photos = get_venue_photos(&data.output_dir, &name);
-                    println!("Found {} photos for {}", photos.len(), name);
-                    VenueResponse {
+            let mut venues_response: Vec<VenueResponse> = Vec::new();
+            for v in venues {
+                let has_pool = v.pool_table_probability >= MIN_RESULT_PROBABILITY && has_evidence(&v);
+                let in_area = search_area.contains(v.latitude, v.longitude);
+
+                println!("Venue '{}' coordinates: ({}, {})", 
+                    v.name, v.latitude, v.longitude);
+
+                if has_pool && in_area {
+                    let photos = get_venue_photos(data.storage.as_ref(), &v.place_id).await;
+                    println!("Found {} photos for {}", photos.len(), v.name);
+                    let route_distance_meters = match &search_area {
+                        SearchArea::Route(route) => Some(route.distance_along(v.latitude, v.longitude)),
+                        _ => None,
+                    };
+                    venues_response.push(VenueResponse {
                         name: v.name,
                         address: v.address,
                         probability: v.pool_table_probability,
                         human_approved: v.human_approved as i64,
+                        human_rejected: v.human_rejected as i64,
+                        image_score: v.image_score,
+                        type_prior: v.type_prior,
+                        score_method: v.score_method,
                         photos,
+                        photos_skipped: v.photos_skipped,
                         place_id: v.place_id,
-                    }
-                })
-                .collect();
+                        found_by: v.found_by,
+                        types: v.types,
+                        business_status: v.business_status,
+                        website: v.website,
+                        phone: v.phone,
+                        rating: v.rating,
+                        price_level: v.price_level,
+                        opening_hours: v.opening_hours,
+                        text_score: v.text_score,
+                        text_snippets: v.text_snippets,
+                        route_distance_meters,
+                    });
+                }
+            }
 
-            println!("Returning {} venues with pool tables in radius", venues_response.len());
+            println!("Returning {} venues with pool tables in the search area", venues_response.len());
             Ok(HttpResponse::Ok()
                 .content_type("application/json")
-                .json(venues_response))
+                .json(SearchResponse {
+                    resolved_location,
+                    venues: venues_response,
+                }))
         },
-        Err(e) => Ok(HttpResponse::InternalServerError()
-            .content_type("application/json")
-            .body(format!("{{\"error\": \"{}\"}}", e)))
+        Err(e) => Ok(HttpResponse::InternalServerError().json(json!({"error": e.to_string()})))
     }
 }
 
-// Add this helper function to calculate distance between two points
-fn calculate_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
-    const EARTH_RADIUS: f64 = 6371000.0; // Earth's radius in meters
-    
-    let lat1_rad = lat1.to_radians();
-    let lat2_rad = lat2.to_radians();
-    let delta_lat = (lat2 - lat1).to_radians();
-    let delta_lon = (lon2 - lon1).to_radians();
-
-    let a = (delta_lat / 2.0).sin() * (delta_lat / 2.0).sin() +
-            lat1_rad.cos() * lat2_rad.cos() *
-            (delta_lon / 2.0).sin() * (delta_lon / 2.0).sin();
-    
-    let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
-    
-    let distance = EARTH_RADIUS * c; // Returns distance in meters
-    
-    println!("Distance calculation: ({}, {}) to ({}, {}) = {} meters", 
-        lat1, lon1, lat2, lon2, distance);
-    
-    distance
-}
-
 // Helper function to get photos for a venue
-fn get_venue_photos(output_dir: &str, venue_name: &str) -> Vec<String> {
-    let sanitized_name = sanitize_filename(venue_name);
-    let folder_path = std::path::Path::new(output_dir).join(&sanitized_name);
-    println!("Looking for photos in: {}", folder_path.display());
-    
-    // Return empty vec if directory doesn't exist instead of panicking
-    match std::fs::read_dir(&folder_path) {
-        Ok(entries) => entries
-            .filter_map(|entry| {
-                entry.ok().and_then(|e| {
-                    let path = e.path();
-                    if path.extension()?.to_str()? == "jpg" {
-                        let encoded_filename = urlencoding::encode(path.file_name()?.to_str()?);
-                        Some(format!("/photos/{}/{}", 
-                            sanitized_name,
-                            encoded_filename))
-                    } else {
-                        None
-                    }
-                })
-            })
-            .collect(),
+async fn get_venue_photos(storage: &dyn PhotoStorage, place_id: &str) -> Vec<VenuePhoto> {
+    let dir_name = venue_dir_name(place_id);
+    println!("Looking for photos of {} in {}", place_id, storage.describe());
+
+    // A venue without a folder just has no photos
+    let mut photos = match venue_photos(storage, place_id).await {
+        Ok(photos) => photos,
         Err(e) => {
-            println!("Could not read directory for '{}': {}", venue_name, e);
+            eprintln!("Error listing photos of {}: {}", place_id, e);
             Vec::new()
         }
-    }
+    };
+    // Strongest evidence first, unclassified photos last
+    photos.sort_by(|a, b| b.score.unwrap_or(-1.0).total_cmp(&a.score.unwrap_or(-1.0)));
+    photos
+        .into_iter()
+        .map(|photo| VenuePhoto {
+            url: format!("/photos/{}/{}", dir_name, urlencoding::encode(&photo.file)),
+            width_px: photo.width_px,
+            height_px: photo.height_px,
+            author_attributions: photo.author_attributions,
+            score: photo.score,
+            class_name: photo.class_name,
+            confidence: photo.confidence,
+            tile: photo.tile,
+        })
+        .collect()
 }
 
 // Add this struct for feedback requests
@@ -268,6 +511,44 @@ pub struct FeedbackRequest {
     place_id: String,
 }
 
+// Storage key of a photo from its gallery URL, or the response to send when it is gone
+async fn photo_key(photo_path: &str, storage: &dyn PhotoStorage) -> Result<String, HttpResponse> {
+    let path = photo_path.trim_start_matches("/photos/");
+    let key = urlencoding::decode(path).map(|key| key.into_owned()).unwrap_or_else(|_| path.to_string());
+    match storage.get(&key).await {
+        Ok(Some(_)) => Ok(key),
+        Ok(None) => {
+            eprintln!("Source file does not exist: {}", key);
+            Err(HttpResponse::NotFound().json(json!({
+                "success": false,
+                "error": format!("Source file not found: {}", key)
+            })))
+        },
+        Err(e) => {
+            eprintln!("Error reading {} from {}: {}", key, storage.describe(), e);
+            Err(HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "error": format!("Failed to read photo: {}", e)
+            })))
+        }
+    }
+}
+
+// Copy a photo into one of the feedback folders, keeping its file name
+async fn copy_feedback_photo(source_key: &str, folder: &str, storage: &dyn PhotoStorage) -> Result<(), HttpResponse> {
+    let filename = source_key.rsplit('/').next().unwrap_or(source_key);
+    let dest_key = format!("{}/{}", folder, filename);
+    println!("Copying {} to {} in {}", source_key, dest_key, storage.describe());
+
+    storage.copy(source_key, &dest_key).await.map_err(|e| {
+        eprintln!("Error copying file to {}: {}", folder, e);
+        HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "error": format!("Failed to copy file: {}", e)
+        }))
+    })
+}
+
 // Add this handler function
 pub async fn handle_feedback(
     feedback: web::Json<FeedbackRequest>,
@@ -308,73 +589,62 @@ pub async fn handle_feedback(
 
     if let Some(index) = venue_index {
         if !feedback.is_positive {
-            // Create no_pool_table_training directory if it doesn't exist
-            let negative_dir = Path::new(&data.output_dir).join("no_pool_table_training");
-            println!("Creating negative directory at: {}", negative_dir.display());
-            
-            if let Err(e) = fs::create_dir_all(&negative_dir) {
-                eprintln!("Error creating negative directory: {}", e);
-                return Ok(HttpResponse::InternalServerError().json(json!({
-                    "success": false,
-                    "error": format!("Failed to create negative directory: {}", e)
-                })));
+            // Move the photo to the negative folder
+            let source_key = match photo_key(&feedback.photo_path, data.storage.as_ref()).await {
+                Ok(key) => key,
+                Err(response) => return Ok(response),
+            };
+            if let Err(response) = copy_feedback_photo(&source_key, NEGATIVE_DIR, data.storage.as_ref()).await {
+                return Ok(response);
             }
-            
-            // Get the source photo path
-            let photo_path = feedback.photo_path.trim_start_matches("/photos/");
-            let source_path = Path::new(&data.output_dir)
-                .join(photo_path);
-
-            if !source_path.exists() {
-                eprintln!("Source file does not exist: {}", source_path.display());
-                return Ok(HttpResponse::NotFound().json(json!({
-                    "success": false,
-                    "error": format!("Source file not found: {}", source_path.display())
-                })));
+
+            // Remove the original file
+            if let Err(e) = data.storage.delete(&source_key).await {
+                eprintln!("Error removing original file: {}", e);
+                // Continue execution - not critical if original remains
             }
-            
-            // Move the photo to negative directory
-            if let Some(filename) = source_path.file_name() {
-                let dest_path = negative_dir.join(filename);
-                println!("Moving file from {} to {}", source_path.display(), dest_path.display());
-                
-                if let Err(e) = fs::copy(&source_path, &dest_path) {
-                    eprintln!("Error copying file to negative directory: {}", e);
+
+            // Zero the photo's score in the manifest, or the next scan would take the
+            // venue's image score from it again
+            let file = source_key.rsplit('/').next().unwrap_or(&source_key);
+            let manifest_score = match PhotoManifest::load(data.storage.as_ref(), &feedback.place_id).await {
+                Ok(Some(mut manifest)) => {
+                    if !manifest.reject(file) {
+                        None
+                    } else if let Err(e) = manifest.save(data.storage.as_ref()).await {
+                        eprintln!("Error saving manifest for {}: {}", feedback.place_id, e);
+                        None
+                    } else {
+                        manifest.image_score()
+                    }
+                },
+                Ok(None) => None,
+                Err(e) => {
+                    eprintln!("Error loading manifest for {}: {}", feedback.place_id, e);
+                    None
+                },
+            };
+
+            // Check if this was the last photo
+            let remaining_photos = match venue_photos(data.storage.as_ref(), &feedback.place_id).await {
+                Ok(photos) => photos.len(),
+                Err(e) => {
+                    eprintln!("Error listing remaining photos: {}", e);
                     return Ok(HttpResponse::InternalServerError().json(json!({
                         "success": false,
-                        "error": format!("Failed to copy file: {}", e)
+                        "error": format!("Failed to list remaining photos: {}", e)
                     })));
                 }
-                
-                // Remove the original file
-                if let Err(e) = fs::remove_file(&source_path) {
-                    eprintln!("Error removing original file: {}", e);
-                    // Continue execution - not critical if original remains
-                }
-            }
-
-            // Check if this was the last photo
-            let venue_dir = Path::new(&data.output_dir)
-                .join(sanitize_filename(&feedback.venue_name));
-            
-            let remaining_photos = match fs::read_dir(&venue_dir) {
-                Ok(entries) => entries
-                    .filter_map(|e| e.ok())
-                    .filter(|e| {
-                        e.path().extension()
-                            .and_then(|ext| ext.to_str())
-                            .map(|ext| ext == "jpg")
-                            .unwrap_or(false)
-                    })
-                    .count(),
-                Err(_) => 0
             };
 
             println!("Remaining photos for venue: {}", remaining_photos);
 
-            // If no photos remain, update venue probability to 0
-            if remaining_photos == 0 {
-                collection.venues[index].pool_table_probability = 0.0;
+            // If no photos remain, the images no longer show a pool table; otherwise
+            // the best photo left decides
+            let image_score = if remaining_photos == 0 { Some(0.0) } else { manifest_score };
+            if image_score.is_some() && image_score != collection.venues[index].image_score {
+                collection.venues[index].image_score = image_score;
+                rescore_venue(&mut collection.venues[index], &data.settings.fusion);
                 collection.venues[index].processed_date = Utc::now();
                 
                 if let Err(e) = collection.save_to_json(db_path) {
@@ -384,57 +654,30 @@ pub async fn handle_feedback(
                         "error": format!("Failed to update venue database: {}", e)
                     })));
                 }
-                println!("Updated venue probability to 0 as all photos were removed");
+                println!("Updated image evidence to {:?} after the rejection", image_score);
             }
 
-            return Ok(HttpResponse::Ok().json(json!({
+            Ok(HttpResponse::Ok().json(json!({
                 "success": true,
                 "message": if remaining_photos == 0 {
                     "Thanks for your feedback. All photos have been removed and venue has been marked as not having pool tables."
                 } else {
                     "Thanks for your feedback to help our training."
                 }
-            })));
+            })))
         } else {
-            // Create confirmed_pool_tables directory if it doesn't exist
-            let confirmed_dir = Path::new(&data.output_dir).join("confirmed_pool_tables");
-            println!("Creating confirmed directory at: {}", confirmed_dir.display());
-            
-            if let Err(e) = fs::create_dir_all(&confirmed_dir) {
-                eprintln!("Error creating confirmed directory: {}", e);
-                return Ok(HttpResponse::InternalServerError().json(json!({
-                    "success": false,
-                    "error": format!("Failed to create confirmed directory: {}", e)
-                })));
-            }
-
-            // Copy the photo to confirmed directory
-            let photo_path = feedback.photo_path.trim_start_matches("/photos/");
-            let source_path = Path::new(&data.output_dir)
-                .join(photo_path);
-            if !source_path.exists() {
-                eprintln!("Source file does not exist: {}", source_path.display());
-                return Ok(HttpResponse::NotFound().json(json!({
-                    "success": false,
-                    "error": format!("Source file not found: {}", source_path.display())
-                })));
-            }
-            
-            if let Some(filename) = source_path.file_name() {
-                let dest_path = confirmed_dir.join(filename);
-                println!("Copying file from {} to {}", source_path.display(), dest_path.display());
-                
-                if let Err(e) = fs::copy(&source_path, &dest_path) {
-                    eprintln!("Error copying file to confirmed directory: {}", e);
-                    return Ok(HttpResponse::InternalServerError().json(json!({
-                        "success": false,
-                        "error": format!("Failed to copy file: {}", e)
-                    })));
-                }
+            // Copy the photo to the confirmed folder
+            let source_key = match photo_key(&feedback.photo_path, data.storage.as_ref()).await {
+                Ok(key) => key,
+                Err(response) => return Ok(response),
+            };
+            if let Err(response) = copy_feedback_photo(&source_key, CONFIRMED_DIR, data.storage.as_ref()).await {
+                return Ok(response);
             }
 
             // Update venue in database
             collection.venues[index].human_approved += 1;
+            rescore_venue(&mut collection.venues[index], &data.settings.fusion);
             let approval_count = collection.venues[index].human_approved;
             println!("Updated approval count for {} to {}", feedback.venue_name, approval_count);
             
@@ -448,10 +691,10 @@ pub async fn handle_feedback(
             }
             
             println!("Successfully saved database with updated approval count");
-            return Ok(HttpResponse::Ok().json(json!({
+            Ok(HttpResponse::Ok().json(json!({
                 "success": true,
                 "message": format!("Thank you! This venue has been approved {} times.", approval_count)
-            })));
+            })))
         }
     } else {
         eprintln!("Venue not found in database: '{}'", feedback.venue_name);
@@ -459,27 +702,13 @@ pub async fn handle_feedback(
         for venue in &collection.venues {
             println!("  - '{}'", venue.name);
         }
-        return Ok(HttpResponse::NotFound().json(json!({
+        Ok(HttpResponse::NotFound().json(json!({
             "success": false,
             "error": "Venue not found in database"
-        })));
+        })))
     }
 }
 
-// Add this function to sanitize filenames
-fn sanitize_filename(name: &str) -> String {
-    // Replace forward slashes and other problematic characters with underscores
-    name.replace('/', "_")
-        .replace('\\', "_")
-        .replace(':', "_")
-        .replace('*', "_")
-        .replace('?', "_")
-        .replace('"', "_")
-        .replace('<', "_")
-        .replace('>', "_")
-        .replace('|', "_")
-}
-
 // Add this new struct for venue-level feedback
 #[derive(Deserialize)]
 pub struct VenueFeedbackRequest {
@@ -491,6 +720,7 @@ pub struct VenueFeedbackRequest {
 // Add this new handler function
 pub async fn handle_venue_feedback(
     feedback: web::Json<VenueFeedbackRequest>,
+    data: web::Data<AppState>,
 ) -> Result<HttpResponse> {
     println!("Starting venue feedback handler");
     println!("Received feedback for venue: {} (place_id: {})", feedback.venue_name, feedback.place_id);
@@ -523,11 +753,11 @@ pub async fn handle_venue_feedback(
             let approval_count = collection.venues[index].human_approved;
             println!("Updated approval count for {} to {}", feedback.venue_name, approval_count);
         } else {
-            // Set probability to 0 for negative feedback
-            collection.venues[index].pool_table_probability = 0.0;
+            collection.venues[index].human_rejected += 1;
             collection.venues[index].processed_date = Utc::now();
-            println!("Set pool table probability to 0 for {}", feedback.venue_name);
+            println!("Recorded rejection for {}", feedback.venue_name);
         }
+        rescore_venue(&mut collection.venues[index], &data.settings.fusion);
         
         // Save updated database
         if let Err(e) = collection.save_to_json(db_path) {
@@ -539,16 +769,33 @@ pub async fn handle_venue_feedback(
         }
         
         println!("Successfully saved database with updates");
-        return Ok(HttpResponse::Ok().json(json!({
+        Ok(HttpResponse::Ok().json(json!({
             "success": true,
             "message": "Thank you for your feedback!"
-        })));
+        })))
     } else {
         eprintln!("Venue not found in database: '{}'", feedback.venue_name);
-        return Ok(HttpResponse::NotFound().json(json!({
+        Ok(HttpResponse::NotFound().json(json!({
             "success": false,
             "error": "Venue not found in database"
-        })));
+        })))
+    }
+}
+
+// Photos and manifests straight from storage, wherever it lives
+pub async fn serve_photo(
+    key: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    match data.storage.get(&key).await {
+        Ok(Some(bytes)) => Ok(HttpResponse::Ok()
+            .content_type(content_type_for(&key))
+            .body(bytes)),
+        Ok(None) => Ok(HttpResponse::NotFound().finish()),
+        Err(e) => {
+            eprintln!("Error serving photo {}: {}", key, e);
+            Ok(HttpResponse::NotFound().finish())
+        }
     }
 }
 
@@ -584,6 +831,10 @@ pub async fn start_server(state: AppState) -> std::io::Result<()> {
                 web::resource("/api/search")
                     .route(web::post().to(search_venues))
             )
+            .service(
+                web::resource("/api/search-route")
+                    .route(web::post().to(search_route))
+            )
             .service(
                 web::resource("/api/feedback")
                     .route(web::post().to(handle_feedback))
@@ -594,8 +845,8 @@ pub async fn start_server(state: AppState) -> std::io::Result<()> {
             )
             // Serve static files first
             .service(
-                Files::new("/photos", &state.output_dir)
-                    .use_last_modified(true)
+                web::resource("/photos/{key:.*}")
+                    .route(web::get().to(serve_photo))
             )
             .service(
                 Files::new("/", "./static")
@@ -607,4 +858,51 @@ pub async fn start_server(state: AppState) -> std::io::Result<()> {
     .client_request_timeout(std::time::Duration::from_secs(900))
     .run()
     .await
-} 
\ No newline at end of file
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::ClassifierBackend;
+
+    #[test]
+    fn web_searches_use_the_config_files_settings() {
+        let path = std::env::temp_dir().join(format!("racknroll_web_settings_{}.yaml", std::process::id()));
+        std::fs::write(&path, "place_types: [bar]
+fusion:
+  method: log_odds
+  text_weight: 0.5
+classifier:
+  backend: mock
+  fixture_path: data/mock_classifier.json
+retry:
+  max_attempts: 7
+planning:
+  discovery_cache_hours: 3
+coverage:
+  max_depth: 2
+").unwrap();
+        let settings = SharedSettings::from_file(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let options: SearchOptions = serde_json::from_value(json!({
+            "months_threshold": 6, "save_negative": false, "reprocess_all": false, "tiled": true, "early_stop": true,
+        })).unwrap();
+        let config = options.to_config(None, None, &settings);
+        assert_eq!(config.fusion.method, FusionMethod::LogOdds);
+        assert_eq!(config.fusion.text_weight, 0.5);
+        // Unset fields keep their defaults
+        assert_eq!(config.fusion.image_weight, 1.0);
+        assert_eq!(config.classifier.backend, ClassifierBackend::Mock);
+        assert_eq!(config.retry.max_attempts, 7);
+        assert_eq!(config.planning.discovery_cache_hours, 3);
+        // The request's own switches win over the file
+        assert!(config.coverage.tiled);
+        assert_eq!(config.coverage.max_depth, 2);
+        assert!(config.early_stop.enabled);
+
+        let defaults = SharedSettings::from_file("no/such/config.yaml").unwrap();
+        assert_eq!(defaults.fusion.method, FusionMethod::NoisyOr);
+        assert_eq!(defaults.place_fields, default_place_fields());
+    }
+}
@@ -0,0 +1,281 @@
+// Runs a whole search against a local stand-in for the Places and OAuth endpoints,
+// with the mock classifier scoring the photos, and checks the venue database it saves.
+
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use serde_json::{json, Value};
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+const API_KEY: &str = "stub-api-key";
+const ACCESS_TOKEN: &str = "stub-access-token";
+
+// Every request the stub answered, as "METHOD path"
+#[derive(Default)]
+struct RequestLog {
+    requests: Mutex<Vec<String>>,
+}
+
+impl RequestLog {
+    fn record(&self, request: &HttpRequest) {
+        self.requests.lock().unwrap().push(format!("{} {}", request.method(), request.path()));
+    }
+
+    fn count(&self, prefix: &str) -> usize {
+        self.requests.lock().unwrap().iter().filter(|r| r.starts_with(prefix)).count()
+    }
+}
+
+fn stub_place(id: &str, name: &str, latitude: f64, longitude: f64) -> Value {
+    json!({
+        "id": id,
+        "displayName": { "text": name },
+        "location": { "latitude": latitude, "longitude": longitude },
+        "formattedAddress": format!("{} Main Street", name),
+        "types": ["bar"],
+        "businessStatus": "OPERATIONAL",
+    })
+}
+
+fn jpeg(colour: [u8; 3]) -> Vec<u8> {
+    let mut bytes = Cursor::new(Vec::new());
+    image::RgbImage::from_pixel(64, 48, image::Rgb(colour))
+        .write_to(&mut bytes, image::ImageOutputFormat::Jpeg(90))
+        .unwrap();
+    bytes.into_inner()
+}
+
+async fn token(request: HttpRequest, form: web::Form<Vec<(String, String)>>, log: web::Data<RequestLog>) -> HttpResponse {
+    log.record(&request);
+    let grant = form.iter().find(|(key, _)| key == "grant_type").map(|(_, value)| value.as_str());
+    if grant != Some("urn:ietf:params:oauth:grant-type:jwt-bearer") {
+        return HttpResponse::BadRequest().json(json!({ "error": "unsupported_grant_type" }));
+    }
+    HttpResponse::Ok().json(json!({ "access_token": ACCESS_TOKEN, "expires_in": 3600 }))
+}
+
+async fn search_nearby(request: HttpRequest, body: web::Json<Value>, log: web::Data<RequestLog>) -> HttpResponse {
+    log.record(&request);
+    if request.headers().get("X-Goog-Api-Key").and_then(|v| v.to_str().ok()) != Some(API_KEY) {
+        return HttpResponse::Forbidden().json(json!({ "error": { "code": 403, "status": "PERMISSION_DENIED" } }));
+    }
+    assert_eq!(body["includedTypes"], json!(["bar"]));
+    HttpResponse::Ok().json(json!({
+        "places": [
+            stub_place("stub_rack_room", "Rack Room", 40.001, -75.001),
+            stub_place("stub_corner_diner", "Corner Diner", 39.999, -74.999),
+        ]
+    }))
+}
+
+fn authorized(request: &HttpRequest) -> bool {
+    request.headers().get("Authorization").and_then(|v| v.to_str().ok()) == Some(&format!("Bearer {}", ACCESS_TOKEN))
+}
+
+// How many photos the details of every place list, p0, p1, ...
+struct PhotoListing(usize);
+
+async fn place_details(
+    request: HttpRequest,
+    path: web::Path<String>,
+    log: web::Data<RequestLog>,
+    listing: web::Data<PhotoListing>,
+) -> HttpResponse {
+    log.record(&request);
+    if !authorized(&request) {
+        return HttpResponse::Unauthorized().json(json!({ "error": { "code": 401, "status": "UNAUTHENTICATED" } }));
+    }
+    let id = path.into_inner();
+    let review = if id == "stub_rack_room" { "Two pool tables in the back room." } else { "Good pancakes." };
+    let photos: Vec<Value> = (0..listing.0)
+        .map(|i| json!({ "name": format!("places/{}/photos/p{}", id, i), "widthPx": 64, "heightPx": 48 }))
+        .collect();
+    HttpResponse::Ok().json(json!({
+        "displayName": { "text": id },
+        "photos": photos,
+        "reviews": [ { "text": { "text": review } } ],
+    }))
+}
+
+async fn photo_media(request: HttpRequest, log: web::Data<RequestLog>) -> HttpResponse {
+    log.record(&request);
+    if !authorized(&request) || !request.query_string().contains(&format!("key={}", API_KEY)) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    HttpResponse::Ok().content_type("image/jpeg").body(jpeg([30, 120, 40]))
+}
+
+// Serves the stub on its own thread and runtime, so the test can block on the binary
+fn start_stub(log: web::Data<RequestLog>, photos_per_place: usize) -> SocketAddr {
+    let listing = web::Data::new(PhotoListing(photos_per_place));
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        actix_web::rt::System::new().block_on(async move {
+            let server = HttpServer::new(move || {
+                App::new()
+                    .app_data(log.clone())
+                    .app_data(listing.clone())
+                    .route("/token", web::post().to(token))
+                    .route("/v1/places:searchNearby", web::post().to(search_nearby))
+                    .route("/v1/places/{id}", web::get().to(place_details))
+                    .route("/v1/places/{id}/photos/{photo}/media", web::get().to(photo_media))
+            })
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+            sender.send(server.addrs()[0]).unwrap();
+            server.run().await.unwrap();
+        })
+    });
+    receiver.recv().unwrap()
+}
+
+fn scratch_folder(name: &str) -> PathBuf {
+    let folder = std::env::temp_dir().join(format!("racknroll_places_stub_{}_{}", name, std::process::id()));
+    if folder.exists() {
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+    std::fs::create_dir_all(&folder).unwrap();
+    folder
+}
+
+// `extra` is appended to the config, e.g. an early_stop section
+fn write_config(folder: &Path, stub: SocketAddr, extra: &str) {
+    let config = format!(r#"
+location:
+  latitude: 40.0
+  longitude: -75.0
+  radius_meters: 1000.0
+
+processing:
+  months_threshold: 6
+  reprocess_all: false
+  save_negative_images: false
+
+place_types:
+  - bar
+
+providers:
+  google: true
+  google_places:
+    base_url: http://{stub}/v1/
+    token_url: http://{stub}/token
+
+retry:
+  max_attempts: 1
+
+classifier:
+  backend: mock
+  fixture_path: {fixture}
+{extra}
+"#, stub = stub, fixture = folder.join("classifier.json").display(), extra = extra);
+    std::fs::write(folder.join("config.yaml"), config).unwrap();
+
+    // Photos of the Rack Room show a table, everything else does not
+    let fixture = json!({
+        "images": { "stub_rack_room": { "class_name": "pool_table", "confidence": 0.92 } },
+        "default": { "class_name": "no_pool_table", "confidence": 0.9 },
+    });
+    std::fs::write(folder.join("classifier.json"), fixture.to_string()).unwrap();
+}
+
+// Run a search in `folder` against its config, with photos kept in folder/photos
+fn run_search(folder: &Path) {
+    let credentials = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/test_service_account.json");
+    let output = Command::new(env!("CARGO_BIN_EXE_RackNRoll"))
+        .current_dir(folder)
+        .args(["--config", "config.yaml"])
+        .env("GOOGLE_PLACES_API_KEY", API_KEY)
+        .env("GOOGLE_PLACES_CRED_PATH", &credentials)
+        .env("OUTPUT_DIRECTORY", folder.join("photos"))
+        .env("YOLO_WEIGHTS_PATH", "unused.pt")
+        .env_remove("PHOTO_STORAGE")
+        .env_remove("CLASSIFIER_BACKEND")
+        .env_remove("CLASSIFIER_FIXTURE")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "search failed:\n{}\n{}",
+        String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn search_saves_classified_venues_from_a_places_stub() {
+    let log = web::Data::new(RequestLog::default());
+    let stub = start_stub(log.clone(), 1);
+    let folder = scratch_folder("search");
+    write_config(&folder, stub, "");
+    let photos = folder.join("photos");
+
+    run_search(&folder);
+
+    // One search, one token for the whole run, details and a photo per venue
+    assert_eq!(log.count("POST /v1/places:searchNearby"), 1);
+    assert_eq!(log.count("POST /token"), 1);
+    assert_eq!(log.count("GET /v1/places/stub_rack_room/photos/p0/media"), 1);
+    assert_eq!(log.count("GET /v1/places/stub_corner_diner/photos/p0/media"), 1);
+
+    let database: Value = serde_json::from_str(&std::fs::read_to_string(folder.join("venues_database.json")).unwrap()).unwrap();
+    let venues = database["venues"].as_array().unwrap();
+    assert_eq!(venues.len(), 2);
+    let venue = |id: &str| venues.iter().find(|v| v["place_id"] == id).unwrap().clone();
+
+    let rack_room = venue("stub_rack_room");
+    assert_eq!(rack_room["name"], "Rack Room");
+    assert_eq!(rack_room["found_by"], json!(["type:bar"]));
+    assert!((rack_room["image_score"].as_f64().unwrap() - 0.92).abs() < 1e-6);
+    assert!(rack_room["text_score"].as_f64().unwrap() > 0.0);
+    assert!(rack_room["pool_table_probability"].as_f64().unwrap() > 0.8, "{}", rack_room);
+
+    let diner = venue("stub_corner_diner");
+    assert_eq!(diner["image_score"].as_f64(), Some(0.0));
+    assert_eq!(diner["text_score"].as_f64(), Some(0.0));
+    assert!(diner["pool_table_probability"].as_f64().unwrap() < 0.5, "{}", diner);
+
+    // The table photo is kept, the negative one removed along with its empty folder
+    assert!(photos.join("stub_rack_room").join("stub_rack_room_0.jpg").exists());
+    assert!(!photos.join("stub_corner_diner").join("stub_corner_diner_0.jpg").exists());
+
+    std::fs::remove_dir_all(&folder).unwrap();
+}
+
+#[test]
+fn early_stop_caps_new_photos_not_ones_scored_before() {
+    let log = web::Data::new(RequestLog::default());
+    let stub = start_stub(log.clone(), 5);
+    let folder = scratch_folder("early_stop");
+    // The classifier never reaches 0.99, so only the cap stops the scan
+    write_config(&folder, stub, "early_stop:\n  enabled: true\n  confidence: 0.99\n  max_photos_per_venue: 2\n");
+
+    // An earlier scan already scored the Rack Room's first photo
+    let venue = folder.join("photos").join("stub_rack_room");
+    std::fs::create_dir_all(&venue).unwrap();
+    let manifest = json!({
+        "place_id": "stub_rack_room",
+        "venue_name": "Rack Room",
+        "photos": [{
+            "file": "stub_rack_room_0.jpg",
+            "photo_name": "places/stub_rack_room/photos/p0",
+            "score": 0.1,
+            "class_name": "no_pool_table",
+            "confidence": 0.9,
+        }],
+    });
+    std::fs::write(venue.join("manifest.json"), manifest.to_string()).unwrap();
+
+    run_search(&folder);
+
+    // p0 is not downloaded again and does not use up the cap: two new photos each
+    let media = |id: &str, photo: usize| log.count(&format!("GET /v1/places/{}/photos/p{}/media", id, photo));
+    assert_eq!((0..5).map(|p| media("stub_rack_room", p)).collect::<Vec<_>>(), vec![0, 1, 1, 0, 0]);
+    assert_eq!((0..5).map(|p| media("stub_corner_diner", p)).collect::<Vec<_>>(), vec![1, 1, 0, 0, 0]);
+
+    let database: Value = serde_json::from_str(&std::fs::read_to_string(folder.join("venues_database.json")).unwrap()).unwrap();
+    let skipped = |id: &str| database["venues"].as_array().unwrap().iter()
+        .find(|v| v["place_id"] == id).unwrap()["photos_skipped"].clone();
+    assert_eq!(skipped("stub_rack_room"), json!(2));
+    assert_eq!(skipped("stub_corner_diner"), json!(3));
+
+    std::fs::remove_dir_all(&folder).unwrap();
+}
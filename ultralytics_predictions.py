@@ -0,0 +1,33 @@
+from ultralytics import YOLO
+import argparse
+import json
+import sys
+
+# Writes ultralytics' top class per photo as JSON, for checking the ONNX runtime in
+# src/native_classifier.rs against it:
+#   python ultralytics_predictions.py --model yolo_weights.onnx --output expected.json photos/*.jpg
+#   YOLO_ONNX_TEST_MODEL=yolo_weights.onnx YOLO_ONNX_TEST_EXPECTED=expected.json cargo test matches_ultralytics
+
+def main():
+    parser = argparse.ArgumentParser(description="Record ultralytics predictions for photos")
+    parser.add_argument("--model", default="./yolo_weights.onnx", help="Model weights or ONNX export")
+    parser.add_argument("--output", default="expected.json", help="Where to write the predictions")
+    parser.add_argument("photos", nargs="+", help="Photos to classify")
+    args = parser.parse_args()
+
+    model = YOLO(args.model, task="classify")
+    predictions = {}
+    for photo in args.photos:
+        result = model(photo, verbose=False)[0]
+        top = int(result.probs.top1)
+        predictions[photo] = {
+            "class_name": result.names[top],
+            "confidence": float(result.probs.top1conf),
+        }
+
+    with open(args.output, "w") as f:
+        json.dump(predictions, f, indent=2)
+    print(f"Wrote {len(predictions)} predictions to {args.output}")
+
+if __name__ == "__main__":
+    sys.exit(main())